@@ -0,0 +1,53 @@
+use nalgebra::Vector3;
+use web_sys::{HtmlOptionElement, HtmlSelectElement};
+
+use crate::camera::{CameraPose, DEFAULT_FOV};
+use crate::dom::select_element;
+
+pub struct Bookmark {
+    pub name: String,
+    pub pose: CameraPose,
+    // Built-in presets can't be deleted
+    pub preset: bool,
+}
+
+impl Bookmark {
+    fn preset(name: &str, position: Vector3<f32>, yaw: f32, pitch: f32) -> Self {
+        Bookmark { name: name.to_string(), pose: CameraPose { position, yaw, pitch, fov: DEFAULT_FOV }, preset: true }
+    }
+}
+
+pub fn home_pose(position: Vector3<f32>) -> CameraPose {
+    CameraPose { position, yaw: -90.0, pitch: 0.0, fov: DEFAULT_FOV }
+}
+
+// The views other than home are placed relative to the cube trajectories spawn in, so they frame
+// every system the way they frame the Lorenz attractor
+pub fn presets(home: Vector3<f32>, spawn_range: f32) -> Vec<Bookmark> {
+    let height = spawn_range * 0.25;
+    vec![
+        Bookmark { name: "home".to_string(), pose: home_pose(home), preset: true },
+        Bookmark::preset("top view", Vector3::new(0.0, spawn_range * 1.2, height), -90.0, -89.0),
+        Bookmark::preset("side view", Vector3::new(spawn_range * 1.2, 0.0, height), 180.0, 0.0),
+        Bookmark::preset("look down the z axis", Vector3::new(0.0, 0.0, spawn_range * 1.5), -90.0, 0.0),
+    ]
+}
+
+// Swap the presets for those of another system, keeping the user's bookmarks after them
+pub fn replace_presets(bookmarks: &mut Vec<Bookmark>, home: Vector3<f32>, spawn_range: f32) {
+    bookmarks.retain(|bookmark| !bookmark.preset);
+    bookmarks.splice(0..0, presets(home, spawn_range));
+}
+
+pub fn bookmark_select() -> HtmlSelectElement {
+    select_element("bookmark-select")
+}
+
+pub fn refresh_bookmark_select(bookmarks: &[Bookmark]) {
+    let select = bookmark_select();
+    select.set_inner_html("");
+    for (i, bookmark) in bookmarks.iter().enumerate() {
+        let option = HtmlOptionElement::new_with_text_and_value(&bookmark.name, &i.to_string()).unwrap();
+        select.append_child(&option).unwrap();
+    }
+}
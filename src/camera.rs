@@ -0,0 +1,222 @@
+use nalgebra::{Matrix4, Point3, Rotation3, Vector2, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+use crate::camera_path::PathPlayback;
+
+pub const MIN_FOV: f32 = 10.0;
+pub const MAX_FOV: f32 = 120.0;
+pub const DEFAULT_FOV: f32 = 60.0;
+pub const DEFAULT_NEAR: f32 = 0.1;
+pub const DEFAULT_FAR: f32 = 30000.0;
+// Duration of a fly-to animation in milliseconds
+pub const TRANSITION_DURATION: f64 = 1500.0;
+pub const DEFAULT_FOLLOW_DISTANCE: f32 = 30.0;
+// Time constant of the chase camera in seconds
+pub const DEFAULT_FOLLOW_SMOOTHING: f32 = 0.3;
+// How far above the followed trajectory the chase camera sits, relative to its distance
+const FOLLOW_HEIGHT: f32 = 0.2;
+// Turntable speed in degrees per second
+pub const DEFAULT_TURNTABLE_SPEED: f32 = 10.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CameraPose {
+    pub position: Vector3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+}
+
+impl CameraPose {
+    pub fn lerp(&self, other: &CameraPose, t: f32) -> CameraPose {
+        // Take the short way around when interpolating yaw
+        let yaw_delta = (other.yaw - self.yaw + 180.0).rem_euclid(360.0) - 180.0;
+        CameraPose {
+            position: self.position.lerp(&other.position, t),
+            yaw: self.yaw + yaw_delta * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+            fov: self.fov + (other.fov - self.fov) * t,
+        }
+    }
+}
+
+pub struct CameraTransition {
+    from: CameraPose,
+    to: CameraPose,
+    // Set on the first update so that callers don't need a clock
+    start: Option<f64>,
+    duration: f64,
+}
+
+pub struct Camera {
+    pub position: Vector3<f32>,
+    pub front: Vector3<f32>,
+    pub up: Vector3<f32>,
+    // Orientation in degrees
+    pub yaw: f32,
+    pub pitch: f32,
+    // Vertical field of view in degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    pub aspect: f32,
+    pub transition: Option<CameraTransition>,
+    // Id of the trajectory the camera is chasing
+    pub follow: Option<u32>,
+    pub follow_distance: f32,
+    pub follow_smoothing: f32,
+    // Centre of the turntable orbit while it is running
+    pub turntable: Option<Vector3<f32>>,
+    pub turntable_speed: f32,
+    pub playback: Option<PathPlayback>,
+}
+
+impl Camera {
+    pub fn new(position: Vector3<f32>) -> Self {
+        let mut camera = Camera {
+            position,
+            front: Vector3::new(0.0, 0.0, -1.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            yaw: -90.0,
+            pitch: 0.0,
+            fov: DEFAULT_FOV,
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
+            aspect: 1.0,
+            transition: None,
+            follow: None,
+            follow_distance: DEFAULT_FOLLOW_DISTANCE,
+            follow_smoothing: DEFAULT_FOLLOW_SMOOTHING,
+            turntable: None,
+            turntable_speed: DEFAULT_TURNTABLE_SPEED,
+            playback: None,
+        };
+        camera.set_orientation(-90.0, 0.0);
+        camera
+    }
+
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-89.0, 89.0);
+        self.front = Vector3::new(f32::cos(self.yaw.to_radians()) * f32::cos(self.pitch.to_radians()),
+                                  f32::sin(self.pitch.to_radians()),
+                                  f32::sin(self.yaw.to_radians()) * f32::cos(self.pitch.to_radians()));
+    }
+
+    pub fn rotate(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        self.set_orientation(self.yaw + delta_yaw, self.pitch + delta_pitch);
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose { position: self.position, yaw: self.yaw, pitch: self.pitch, fov: self.fov }
+    }
+
+    pub fn set_pose(&mut self, pose: &CameraPose) {
+        self.position = pose.position;
+        self.set_orientation(pose.yaw, pose.pitch);
+        self.set_fov(pose.fov);
+    }
+
+    pub fn fly_to(&mut self, pose: &CameraPose) {
+        self.take_control();
+        self.transition = Some(CameraTransition { from: self.pose(), to: *pose, start: None, duration: TRANSITION_DURATION });
+    }
+
+    // Hand the camera back to the user, stopping any automatic motion
+    pub fn take_control(&mut self) {
+        self.transition = None;
+        self.follow = None;
+        self.turntable = None;
+        self.playback = None;
+    }
+
+    pub fn play(&mut self, playback: PathPlayback) {
+        self.take_control();
+        self.playback = Some(playback);
+    }
+
+    pub fn start_turntable(&mut self, centre: Vector3<f32>) {
+        self.take_control();
+        self.turntable = Some(centre);
+    }
+
+    // Swing the camera around a vertical axis through `centre`, keeping it pointed at the centre
+    pub fn orbit(&mut self, centre: Vector3<f32>, degrees: f32) {
+        let rotation = Rotation3::from_axis_angle(&Vector3::y_axis(), degrees.to_radians());
+        self.position = centre + rotation * (self.position - centre);
+        let to_centre = (centre - self.position).try_normalize(f32::EPSILON).unwrap_or(self.front);
+        self.set_orientation(to_centre.z.atan2(to_centre.x).to_degrees(), to_centre.y.clamp(-1.0, 1.0).asin().to_degrees());
+    }
+
+    // Ride behind `head` looking along `heading`, `elapsed` is the frame time in seconds
+    pub fn chase(&mut self, head: Vector3<f32>, heading: Vector3<f32>, elapsed: f32) {
+        let alpha = if self.follow_smoothing > 0.0 {
+            1.0 - f32::exp(-elapsed / self.follow_smoothing)
+        } else {
+            1.0
+        };
+
+        let target = head - heading * self.follow_distance + self.up * self.follow_distance * FOLLOW_HEIGHT;
+        self.position = self.position.lerp(&target, alpha);
+
+        let front = self.front.lerp(&heading, alpha).try_normalize(f32::EPSILON).unwrap_or(heading);
+        self.set_orientation(front.z.atan2(front.x).to_degrees(), front.y.clamp(-1.0, 1.0).asin().to_degrees());
+    }
+
+    // Advance any running automatic motion, `now` is in milliseconds and `elapsed` in seconds
+    pub fn update(&mut self, now: f64, elapsed: f32) {
+        if let Some(transition) = self.transition.as_mut() {
+            let start = *transition.start.get_or_insert(now);
+            let t = ((now - start) / transition.duration).clamp(0.0, 1.0) as f32;
+            // Smoothstep easing
+            let eased = t * t * (3.0 - 2.0 * t);
+            let pose = transition.from.lerp(&transition.to, eased);
+            self.set_pose(&pose);
+            if t >= 1.0 {
+                self.transition = None;
+            }
+        } else if let Some(playback) = self.playback.as_mut() {
+            match playback.pose_at(now) {
+                Some((pose, finished)) => {
+                    self.set_pose(&pose);
+                    if finished {
+                        self.playback = None;
+                    }
+                },
+                None => self.playback = None,
+            }
+        } else if let Some(centre) = self.turntable {
+            self.orbit(centre, self.turntable_speed * elapsed);
+        }
+    }
+
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(MIN_FOV, MAX_FOV);
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.front.cross(&self.up)
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        let target = self.position + self.front;
+        Matrix4::look_at_rh(&Point3::from(self.position), &Point3::from(target), &self.up)
+    }
+
+    pub fn projection(&self) -> Matrix4<f32> {
+        Matrix4::new_perspective(self.aspect, self.fov.to_radians(), self.near, self.far)
+    }
+
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection() * self.view()
+    }
+
+    // Direction of the ray leaving the camera through a point in normalised device coordinates
+    pub fn ray_direction(&self, ndc: Vector2<f32>) -> Option<Vector3<f32>> {
+        let inverse = self.view_projection().try_inverse()?;
+        let near = inverse * Vector4::new(ndc.x, ndc.y, -1.0, 1.0);
+        let far = inverse * Vector4::new(ndc.x, ndc.y, 1.0, 1.0);
+        let near = near.xyz() / near.w;
+        let far = far.xyz() / far.w;
+        (far - near).try_normalize(f32::EPSILON)
+    }
+}
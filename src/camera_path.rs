@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+use crate::camera::CameraPose;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Keyframe {
+    // Seconds from the start of the path
+    pub time: f32,
+    pub pose: CameraPose,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p1)
+        + (-p0 + p2) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t3)
+}
+
+// Shift `yaw` by whole turns so it is within 180 degrees of `reference`
+fn unwrap_yaw(yaw: f32, reference: f32) -> f32 {
+    reference + (yaw - reference + 180.0).rem_euclid(360.0) - 180.0
+}
+
+impl CameraPath {
+    // Keyframes are kept sorted by time, recording at an existing time replaces that keyframe
+    pub fn record(&mut self, time: f32, pose: CameraPose) {
+        match self.keyframes.iter().position(|keyframe| keyframe.time >= time) {
+            Some(i) if self.keyframes[i].time == time => self.keyframes[i].pose = pose,
+            Some(i) => self.keyframes.insert(i, Keyframe { time, pose }),
+            None => self.keyframes.push(Keyframe { time, pose }),
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let n = self.keyframes.len();
+        if n == 0 {
+            return None;
+        }
+        if n == 1 || time <= self.keyframes[0].time {
+            return Some(self.keyframes[0].pose);
+        }
+        if time >= self.duration() {
+            return Some(self.keyframes[n - 1].pose);
+        }
+
+        let i = self.keyframes.iter().rposition(|keyframe| keyframe.time <= time).unwrap();
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        // Duplicate the end points so the spline passes through the first and last keyframes
+        let k0 = &self.keyframes[i.saturating_sub(1)];
+        let k3 = &self.keyframes[(i + 2).min(n - 1)];
+        let span = k2.time - k1.time;
+        let t = if span > 0.0 { (time - k1.time) / span } else { 0.0 };
+
+        let (p0, p1, p2, p3) = (k0.pose, k1.pose, k2.pose, k3.pose);
+        let y1 = p1.yaw;
+        let y0 = unwrap_yaw(p0.yaw, y1);
+        let y2 = unwrap_yaw(p2.yaw, y1);
+        let y3 = unwrap_yaw(p3.yaw, y2);
+
+        let mut position = p1.position;
+        for axis in 0..3 {
+            position[axis] = catmull_rom(p0.position[axis], p1.position[axis], p2.position[axis], p3.position[axis], t);
+        }
+
+        Some(CameraPose {
+            position,
+            yaw: catmull_rom(y0, y1, y2, y3, t),
+            pitch: catmull_rom(p0.pitch, p1.pitch, p2.pitch, p3.pitch, t),
+            fov: catmull_rom(p0.fov, p1.fov, p2.fov, p3.fov, t),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let mut path: CameraPath = serde_json::from_str(text).map_err(|e| format!("Invalid camera path: {}", e))?;
+        if path.keyframes.iter().any(|keyframe| !keyframe.time.is_finite()) {
+            return Err(String::from("Invalid camera path: keyframe times must be finite"));
+        }
+        path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(path)
+    }
+}
+
+pub struct PathPlayback {
+    path: CameraPath,
+    start: Option<f64>,
+    looping: bool,
+}
+
+impl PathPlayback {
+    pub fn new(path: CameraPath, looping: bool) -> Self {
+        PathPlayback { path, start: None, looping }
+    }
+
+    // Returns the pose and whether a non-looping path has finished, `now` is in milliseconds
+    pub fn pose_at(&mut self, now: f64) -> Option<(CameraPose, bool)> {
+        let start = *self.start.get_or_insert(now);
+        let time = ((now - start) / 1000.0) as f32;
+        let duration = self.path.duration();
+        let finished = !self.looping && time >= duration;
+        let time = if self.looping && duration > 0.0 { time % duration } else { time };
+        self.path.sample(time).map(|pose| (pose, finished))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    fn pose(x: f32, yaw: f32) -> CameraPose {
+        CameraPose { position: Vector3::new(x, 0.0, 0.0), yaw, pitch: 0.0, fov: 45.0 }
+    }
+
+    // Keyframes one second apart moving one unit along x each
+    fn path(count: usize) -> CameraPath {
+        let mut path = CameraPath::default();
+        for i in 0..count {
+            path.record(i as f32, pose(i as f32, 0.0));
+        }
+        path
+    }
+
+    #[test]
+    fn passes_through_every_keyframe() {
+        let path = path(4);
+        for keyframe in &path.keyframes {
+            let sampled = path.sample(keyframe.time).unwrap();
+            assert!((sampled.position - keyframe.pose.position).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn clamps_outside_the_path() {
+        let three = path(3);
+        assert_eq!(three.sample(-1.0).unwrap().position.x, 0.0);
+        assert_eq!(three.sample(10.0).unwrap().position.x, 2.0);
+        assert!(CameraPath::default().sample(0.0).is_none());
+        assert_eq!(path(1).sample(5.0).unwrap().position.x, 0.0);
+    }
+
+    #[test]
+    fn end_segments_repeat_the_end_keyframes() {
+        let path = path(4);
+        // Between evenly spaced keyframes the spline is linear
+        assert!((path.sample(1.5).unwrap().position.x - 1.5).abs() < 1e-6);
+        // With the first keyframe doubled the first segment eases out of it, and symmetrically
+        // into the last one
+        assert!((path.sample(0.5).unwrap().position.x - 0.4375).abs() < 1e-6);
+        assert!((path.sample(2.5).unwrap().position.x - 2.5625).abs() < 1e-6);
+    }
+
+    #[test]
+    fn yaw_takes_the_short_way_around() {
+        let mut path = CameraPath::default();
+        path.record(0.0, pose(0.0, 170.0));
+        path.record(1.0, pose(0.0, -170.0));
+        let yaw = path.sample(0.5).unwrap().yaw;
+        assert!((yaw.rem_euclid(360.0) - 180.0).abs() < 1e-3, "{}", yaw);
+    }
+
+    #[test]
+    fn recording_keeps_keyframes_sorted_and_replaces_equal_times() {
+        let mut path = CameraPath::default();
+        path.record(2.0, pose(2.0, 0.0));
+        path.record(0.0, pose(0.0, 0.0));
+        path.record(1.0, pose(1.0, 0.0));
+        path.record(1.0, pose(5.0, 0.0));
+        let times: Vec<f32> = path.keyframes.iter().map(|keyframe| keyframe.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0]);
+        assert_eq!(path.keyframes[1].pose.position.x, 5.0);
+        assert_eq!(path.duration(), 2.0);
+    }
+
+    #[test]
+    fn json_round_trip_sorts_keyframes() {
+        let text = r#"{"keyframes": [{"time": 1.0, "pose": {"position": [1.0, 0.0, 0.0], "yaw": 0.0, "pitch": 0.0, "fov": 45.0}},
+                                      {"time": 0.0, "pose": {"position": [0.0, 0.0, 0.0], "yaw": 0.0, "pitch": 0.0, "fov": 45.0}}]}"#;
+        let path = CameraPath::from_json(text).unwrap();
+        assert_eq!(path.keyframes[0].time, 0.0);
+        let again = CameraPath::from_json(&path.to_json()).unwrap();
+        assert_eq!(again.keyframes.len(), 2);
+        assert!(CameraPath::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::dom::{input_element, select_element};
+
+// Format a colour with components in [0, 1] as "#rrggbb" for colour inputs
+pub fn to_hex(colour: &Vector3<f32>) -> String {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(colour.x), channel(colour.y), channel(colour.z))
+}
+
+pub fn from_hex(hex: &str) -> Option<Vector3<f32>> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok().map(|c| c as f32 / 255.0);
+    Some(Vector3::new(channel(0)?, channel(2)?, channel(4)?))
+}
+
+// Hue, saturation and value all in [0, 1]
+pub fn hsv_to_rgb(h: f32, s: f32, v: f32) -> Vector3<f32> {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    Vector3::new(r + m, g + m, b + m)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColourScheme {
+    Random,
+    // Evenly spread hues in spawn order
+    Rainbow,
+    Single(Vector3<f32>),
+}
+
+impl ColourScheme {
+    // `index` counts the trajectories spawned so far
+    pub fn colour<R: Rng>(&self, index: u64, rng: &mut R) -> Vector3<f32> {
+        match self {
+            ColourScheme::Random => Vector3::new(rng.gen(), rng.gen(), rng.gen()),
+            // Stepping by the golden ratio keeps neighbouring trajectories apart in hue
+            ColourScheme::Rainbow => hsv_to_rgb(index as f32 * 0.618_034, 0.8, 1.0),
+            ColourScheme::Single(colour) => *colour,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        match self {
+            ColourScheme::Random => String::from("random"),
+            ColourScheme::Rainbow => String::from("rainbow"),
+            ColourScheme::Single(colour) => format!("single-{}", &to_hex(colour)[1..]),
+        }
+    }
+
+    pub fn decode(text: &str) -> Option<Self> {
+        match text {
+            "random" => Some(ColourScheme::Random),
+            "rainbow" => Some(ColourScheme::Rainbow),
+            _ => text.strip_prefix("single-").and_then(|hex| from_hex(&format!("#{}", hex))).map(ColourScheme::Single),
+        }
+    }
+}
+
+pub fn read_colour_scheme() -> Option<ColourScheme> {
+    match select_element("colour-scheme").value().as_str() {
+        "single" => from_hex(&input_element("colour-single").value()).map(ColourScheme::Single),
+        other => ColourScheme::decode(other),
+    }
+}
+
+pub fn show_colour_scheme(scheme: &ColourScheme) {
+    match scheme {
+        ColourScheme::Single(colour) => {
+            select_element("colour-scheme").set_value("single");
+            input_element("colour-single").set_value(&to_hex(colour));
+        },
+        other => select_element("colour-scheme").set_value(&other.encode()),
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use nalgebra::Vector3;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+
+use crate::simulation::Simulation;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Plane {
+    Xy,
+    Xz,
+    Yz,
+}
+
+pub const PLANES: [Plane; 3] = [Plane::Xy, Plane::Xz, Plane::Yz];
+
+impl Plane {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Plane::Xy => "xy",
+            Plane::Xz => "xz",
+            Plane::Yz => "yz",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Plane> {
+        PLANES.iter().copied().find(|plane| plane.id() == id)
+    }
+
+    // Coordinates along the horizontal and vertical axes of the image
+    fn axes(&self) -> (usize, usize) {
+        match self {
+            Plane::Xy => (0, 1),
+            Plane::Xz => (0, 2),
+            Plane::Yz => (1, 2),
+        }
+    }
+
+    fn index(&self) -> usize {
+        PLANES.iter().position(|plane| plane == self).unwrap()
+    }
+}
+
+// Fraction of the initial bounding box added on every side
+const MARGIN: f32 = 0.25;
+
+// Log-scaled colour ramp from black through purple and orange to white
+const HEAT_STOPS: [[f32; 3]; 5] = [[0.0, 0.0, 0.0], [0.3, 0.05, 0.5], [0.9, 0.3, 0.1], [1.0, 0.9, 0.3], [1.0, 1.0, 1.0]];
+
+fn heat_colour(t: f32) -> [u8; 3] {
+    let scaled = t.clamp(0.0, 1.0) * (HEAT_STOPS.len() - 1) as f32;
+    let i = (scaled as usize).min(HEAT_STOPS.len() - 2);
+    let f = scaled - i as f32;
+    let mut colour = [0; 3];
+    for (c, value) in colour.iter_mut().enumerate() {
+        *value = ((HEAT_STOPS[i][c] + (HEAT_STOPS[i + 1][c] - HEAT_STOPS[i][c]) * f) * 255.0).round() as u8;
+    }
+    colour
+}
+
+// Visit counts of every state on a grid over each coordinate plane, an estimate of the natural measure
+pub struct DensityHistogram {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    // Bins along the longer side of each plane
+    resolution: usize,
+    // One histogram per plane, indexed like PLANES, rows run along the second axis
+    counts: [Vec<u32>; 3],
+    pub total: u64,
+    // States that fell outside the grid and weren't counted
+    pub outside: u64,
+    // Steps of each trajectory already counted
+    seen: HashMap<u32, u64>,
+}
+
+impl DensityHistogram {
+    // Covers the bounding box of `points` with a margin, None without points to size it from
+    pub fn around<'a, I: Iterator<Item = &'a Vector3<f32>>>(points: I, resolution: usize) -> Option<Self> {
+        let mut points = points.peekable();
+        let first = **points.peek()?;
+        let (min, max) = points.fold((first, first), |(min, max), point| (min.inf(point), max.sup(point)));
+        let margin = (max - min).map(|span| span.max(1.0) * MARGIN);
+        let mut histogram = DensityHistogram {
+            min: min - margin,
+            max: max + margin,
+            resolution: resolution.max(1),
+            counts: Default::default(),
+            total: 0,
+            outside: 0,
+            seen: HashMap::new(),
+        };
+        for plane in PLANES {
+            let (width, height) = histogram.size(plane);
+            histogram.counts[plane.index()] = vec![0; width * height];
+        }
+        Some(histogram)
+    }
+
+    // Image size in bins, keeping the aspect ratio of the plane
+    fn size(&self, plane: Plane) -> (usize, usize) {
+        let (a, b) = plane.axes();
+        let span = self.max - self.min;
+        let longer = span[a].max(span[b]);
+        let bins = |length: f32| ((self.resolution as f32 * length / longer).round() as usize).max(1);
+        (bins(span[a]), bins(span[b]))
+    }
+
+    // States outside the grid are only counted in `outside`, "reset density" grows the grid to
+    // the current trails
+    pub fn add(&mut self, state: &Vector3<f32>) {
+        let cell = (state - self.min).component_div(&(self.max - self.min));
+        if !cell.iter().all(|c| (0.0..1.0).contains(c)) {
+            self.outside += 1;
+            return;
+        }
+        for plane in PLANES {
+            let (a, b) = plane.axes();
+            let (width, height) = self.size(plane);
+            let column = (cell[a] * width as f32) as usize;
+            let row = (cell[b] * height as f32) as usize;
+            self.counts[plane.index()][row * width + column] += 1;
+        }
+        self.total += 1;
+    }
+
+    // Count the states every trajectory reached since the last call, trajectories seen for the
+    // first time contribute their head
+    pub fn add_new_states(&mut self, simulation: &Simulation) {
+        let mut seen = HashMap::with_capacity(simulation.trajectories.len());
+        for trajectory in simulation.trajectories.iter().filter(|trajectory| !trajectory.frozen) {
+            let new = match self.seen.get(&trajectory.id) {
+                Some(previous) => trajectory.steps.saturating_sub(*previous) as usize,
+                None => 1,
+            };
+            let new = new.min(trajectory.points.len());
+            for point in &trajectory.points[trajectory.points.len() - new..] {
+                self.add(point);
+            }
+            seen.insert(trajectory.id, trajectory.steps);
+        }
+        self.seen = seen;
+    }
+
+    // Draw one plane as a heatmap of log(1 + count), resizing the canvas to the histogram
+    pub fn render(&self, plane: Plane, canvas: &HtmlCanvasElement) -> Result<(), String> {
+        let (width, height) = self.size(plane);
+        let counts = &self.counts[plane.index()];
+        let scale = (1.0 + counts.iter().copied().max().unwrap_or(0) as f32).ln().max(f32::EPSILON);
+
+        let mut pixels = vec![255u8; width * height * 4];
+        for row in 0..height {
+            // Canvas rows start at the top, the second axis points up
+            let target = (height - 1 - row) * width;
+            for column in 0..width {
+                let colour = heat_colour((1.0 + counts[row * width + column] as f32).ln() / scale);
+                pixels[(target + column) * 4..(target + column) * 4 + 3].copy_from_slice(&colour);
+            }
+        }
+
+        canvas.set_width(width as u32);
+        canvas.set_height(height as u32);
+        let context = canvas
+            .get_context("2d")
+            .ok()
+            .flatten()
+            .ok_or("Could not draw the density")?
+            .dyn_into::<CanvasRenderingContext2d>()
+            .unwrap();
+        let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), width as u32, height as u32)
+            .map_err(|e| format!("Could not create image: {:?}", e))?;
+        context.put_image_data(&image, 0.0, 0.0).map_err(|e| format!("Could not draw the density: {:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributions::InitialDistribution;
+    use crate::integrators::Integrator;
+    use crate::systems::SYSTEMS;
+
+    #[test]
+    fn states_outside_the_grid_are_counted_separately() {
+        let corners = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(10.0, 10.0, 10.0)];
+        let mut histogram = DensityHistogram::around(corners.iter(), 16).unwrap();
+        histogram.add(&Vector3::new(5.0, 5.0, 5.0));
+        histogram.add(&Vector3::new(-100.0, 5.0, 5.0));
+        histogram.add(&Vector3::new(5.0, f32::NAN, 5.0));
+        assert_eq!(histogram.total, 1);
+        assert_eq!(histogram.outside, 2);
+        for plane in PLANES {
+            assert_eq!(histogram.counts[plane.index()].iter().sum::<u32>(), 1);
+        }
+    }
+
+    #[test]
+    fn each_step_is_counted_once() {
+        let corners = [Vector3::repeat(-100.0), Vector3::repeat(100.0)];
+        let mut histogram = DensityHistogram::around(corners.iter(), 16).unwrap();
+        let mut simulation = Simulation::new(&SYSTEMS[0], 10, InitialDistribution::cube(1.0), 0);
+        simulation.spawn(Vector3::new(1.0, 1.0, 1.0), Vector3::zeros());
+        let step = |simulation: &mut Simulation, steps: usize| {
+            for _ in 0..steps {
+                simulation.step(Integrator::Rk4, 0.01, 5, 100.0);
+            }
+        };
+
+        // A new trajectory contributes its head
+        histogram.add_new_states(&simulation);
+        assert_eq!(histogram.total, 1);
+        step(&mut simulation, 3);
+        histogram.add_new_states(&simulation);
+        assert_eq!(histogram.total, 4);
+        // Nothing new, nothing counted
+        histogram.add_new_states(&simulation);
+        assert_eq!(histogram.total, 4);
+        // The trail is trimmed to 5 points, only the 2 newest have not been counted
+        step(&mut simulation, 2);
+        assert_eq!(simulation.trajectories[0].points.len(), 5);
+        histogram.add_new_states(&simulation);
+        assert_eq!(histogram.total, 6);
+    }
+}
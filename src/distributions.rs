@@ -0,0 +1,221 @@
+use nalgebra::Vector3;
+use rand::Rng;
+
+use crate::dom::{html_element, input_element, select_element};
+
+#[derive(Clone, Copy, Debug)]
+pub enum InitialDistribution {
+    UniformBox { centre: Vector3<f32>, size: Vector3<f32> },
+    // Uniform in volume between `radius` and `radius + thickness`
+    SphereShell { centre: Vector3<f32>, radius: f32, thickness: f32 },
+    Gaussian { centre: Vector3<f32>, std_dev: f32 },
+    // `resolution` points per side, handed out in order
+    Grid { centre: Vector3<f32>, size: f32, resolution: u32 },
+    Line { start: Vector3<f32>, end: Vector3<f32> },
+}
+
+const DISTRIBUTIONS: [&str; 5] = ["box", "sphere", "gaussian", "grid", "line"];
+
+fn standard_normal<R: Rng>(rng: &mut R) -> f32 {
+    // Box-Muller, 1 - u keeps the logarithm finite
+    let u1 = 1.0 - rng.gen::<f32>();
+    let u2 = rng.gen::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+fn random_direction<R: Rng>(rng: &mut R) -> Vector3<f32> {
+    loop {
+        let v = Vector3::new(standard_normal(rng), standard_normal(rng), standard_normal(rng));
+        if let Some(direction) = v.try_normalize(f32::EPSILON) {
+            return direction;
+        }
+    }
+}
+
+impl InitialDistribution {
+    pub fn cube(size: f32) -> Self {
+        InitialDistribution::UniformBox { centre: Vector3::zeros(), size: Vector3::repeat(size) }
+    }
+
+    // `index` counts the samples drawn so far, used by the ordered distributions
+    pub fn sample<R: Rng>(&self, index: u64, rng: &mut R) -> Vector3<f32> {
+        match *self {
+            InitialDistribution::UniformBox { centre, size } => {
+                centre + size.component_mul(&Vector3::new(rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5))
+            },
+            InitialDistribution::SphereShell { centre, radius, thickness } => {
+                let inner = radius.powi(3);
+                let outer = (radius + thickness).powi(3);
+                let r = (inner + (outer - inner) * rng.gen::<f32>()).cbrt();
+                centre + random_direction(rng) * r
+            },
+            InitialDistribution::Gaussian { centre, std_dev } => {
+                centre + Vector3::new(standard_normal(rng), standard_normal(rng), standard_normal(rng)) * std_dev
+            },
+            InitialDistribution::Grid { centre, size, resolution } => {
+                let n = resolution.max(1) as u64;
+                let i = index % (n * n * n);
+                let cell = Vector3::new((i % n) as f32, ((i / n) % n) as f32, (i / (n * n)) as f32);
+                if n == 1 {
+                    centre
+                } else {
+                    centre + (cell / (n - 1) as f32 - Vector3::repeat(0.5)) * size
+                }
+            },
+            InitialDistribution::Line { start, end } => start.lerp(&end, rng.gen::<f32>()),
+        }
+    }
+}
+
+fn number(id: &str) -> Result<f32, String> {
+    input_element(id)
+        .value()
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("{} must be a number", id))
+}
+
+fn non_negative(id: &str) -> Result<f32, String> {
+    let value = number(id)?;
+    if value < 0.0 {
+        return Err(format!("{} must not be negative", id));
+    }
+    Ok(value)
+}
+
+fn vector(prefix: &str) -> Result<Vector3<f32>, String> {
+    Ok(Vector3::new(number(&format!("{}-x", prefix))?, number(&format!("{}-y", prefix))?, number(&format!("{}-z", prefix))?))
+}
+
+// Show only the parameters of the chosen distribution
+pub fn show_distribution_parameters() {
+    let chosen = select_element("distribution-select").value();
+    for name in DISTRIBUTIONS {
+        html_element(&format!("dist-{}", name)).set_hidden(name != chosen);
+    }
+}
+
+pub fn read_distribution() -> Result<InitialDistribution, String> {
+    match select_element("distribution-select").value().as_str() {
+        "box" => {
+            let size = vector("dist-box-size")?;
+            if size.iter().any(|s| *s < 0.0) {
+                return Err(String::from("box size must not be negative"));
+            }
+            Ok(InitialDistribution::UniformBox { centre: vector("dist-box-centre")?, size })
+        },
+        "sphere" => Ok(InitialDistribution::SphereShell {
+            centre: vector("dist-sphere-centre")?,
+            radius: non_negative("dist-sphere-radius")?,
+            thickness: non_negative("dist-sphere-thickness")?,
+        }),
+        "gaussian" => Ok(InitialDistribution::Gaussian {
+            centre: vector("dist-gaussian-centre")?,
+            std_dev: non_negative("dist-gaussian-std-dev")?,
+        }),
+        "grid" => {
+            let resolution = number("dist-grid-resolution")?;
+            if resolution < 1.0 || resolution.fract() != 0.0 {
+                return Err(String::from("grid resolution must be a whole number of at least 1"));
+            }
+            Ok(InitialDistribution::Grid {
+                centre: vector("dist-grid-centre")?,
+                size: non_negative("dist-grid-size")?,
+                resolution: resolution as u32,
+            })
+        },
+        "line" => Ok(InitialDistribution::Line { start: vector("dist-line-start")?, end: vector("dist-line-end")? }),
+        other => Err(format!("unknown distribution {}", other)),
+    }
+}
+
+fn show_vector(prefix: &str, v: &Vector3<f32>) {
+    input_element(&format!("{}-x", prefix)).set_value(&v.x.to_string());
+    input_element(&format!("{}-y", prefix)).set_value(&v.y.to_string());
+    input_element(&format!("{}-z", prefix)).set_value(&v.z.to_string());
+}
+
+// Reflect a distribution in the spawn controls
+pub fn show_distribution(distribution: &InitialDistribution) {
+    let name = match *distribution {
+        InitialDistribution::UniformBox { centre, size } => {
+            show_vector("dist-box-centre", &centre);
+            show_vector("dist-box-size", &size);
+            "box"
+        },
+        InitialDistribution::SphereShell { centre, radius, thickness } => {
+            show_vector("dist-sphere-centre", &centre);
+            input_element("dist-sphere-radius").set_value(&radius.to_string());
+            input_element("dist-sphere-thickness").set_value(&thickness.to_string());
+            "sphere"
+        },
+        InitialDistribution::Gaussian { centre, std_dev } => {
+            show_vector("dist-gaussian-centre", &centre);
+            input_element("dist-gaussian-std-dev").set_value(&std_dev.to_string());
+            "gaussian"
+        },
+        InitialDistribution::Grid { centre, size, resolution } => {
+            show_vector("dist-grid-centre", &centre);
+            input_element("dist-grid-size").set_value(&size.to_string());
+            input_element("dist-grid-resolution").set_value(&resolution.to_string());
+            "grid"
+        },
+        InitialDistribution::Line { start, end } => {
+            show_vector("dist-line-start", &start);
+            show_vector("dist-line-end", &end);
+            "line"
+        },
+    };
+    select_element("distribution-select").set_value(name);
+    show_distribution_parameters();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn grid_points_are_handed_out_in_order_and_wrap() {
+        let grid = InitialDistribution::Grid { centre: Vector3::new(1.0, 1.0, 1.0), size: 2.0, resolution: 2 };
+        let mut rng = StdRng::seed_from_u64(1);
+        let points: Vec<Vector3<f32>> = (0..10).map(|index| grid.sample(index, &mut rng)).collect();
+        // x varies fastest, then y, then z
+        assert_eq!(points[0], Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(points[1], Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(points[2], Vector3::new(0.0, 2.0, 0.0));
+        assert_eq!(points[4], Vector3::new(0.0, 0.0, 2.0));
+        assert_eq!(points[7], Vector3::new(2.0, 2.0, 2.0));
+        // Past the last corner the grid starts again
+        assert_eq!(points[8], points[0]);
+        assert_eq!(points[9], points[1]);
+    }
+
+    #[test]
+    fn shell_samples_lie_between_the_radii() {
+        let centre = Vector3::new(1.0, -2.0, 3.0);
+        let shell = InitialDistribution::SphereShell { centre, radius: 2.0, thickness: 0.5 };
+        let mut rng = StdRng::seed_from_u64(2);
+        for index in 0..1000 {
+            let r = (shell.sample(index, &mut rng) - centre).norm();
+            assert!((2.0 - 1e-4..=2.5 + 1e-4).contains(&r), "radius {}", r);
+        }
+    }
+
+    #[test]
+    fn line_samples_reach_both_ends() {
+        let (start, end) = (Vector3::new(-1.0, 0.0, 2.0), Vector3::new(3.0, 4.0, 2.0));
+        let line = InitialDistribution::Line { start, end };
+        let mut rng = StdRng::seed_from_u64(3);
+        let fractions: Vec<f32> = (0..1000).map(|index| {
+            let point = line.sample(index, &mut rng);
+            // Every sample is on the segment
+            assert!(((point - start).norm() + (end - point).norm() - (end - start).norm()).abs() < 1e-4);
+            (point - start).norm() / (end - start).norm()
+        }).collect();
+        assert!(fractions.iter().any(|f| *f < 0.01));
+        assert!(fractions.iter().any(|f| *f > 0.99));
+    }
+}
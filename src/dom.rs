@@ -1,11 +1,13 @@
 use wasm_bindgen::prelude::*;
-use gloo::events::{EventListenerOptions,EventListenerPhase,EventListener};
-use gloo::console::log;
 
 pub fn window() -> web_sys::Window { web_sys::window().expect("no global `window` exists")
 }
 
-pub fn request_animation_frame(f: &Closure<dyn FnMut()>) {
+pub fn local_storage() -> Option<web_sys::Storage> {
+    window().local_storage().ok().flatten()
+}
+
+pub fn request_animation_frame(f: &Closure<dyn FnMut(f64)>) {
     window()
         .request_animation_frame(f.as_ref().unchecked_ref())
         .expect("should register `requestAnimationFrame` OK");
@@ -21,3 +23,85 @@ pub fn body() -> web_sys::HtmlElement {
     document().body().expect("document should have a body")
 }
 
+
+pub fn input_element(id: &str) -> web_sys::HtmlInputElement {
+    document()
+        .get_element_by_id(id)
+        .unwrap_or_else(|| panic!("no element with id `{}`", id))
+        .dyn_into::<web_sys::HtmlInputElement>()
+        .unwrap()
+}
+
+pub fn select_element(id: &str) -> web_sys::HtmlSelectElement {
+    document()
+        .get_element_by_id(id)
+        .unwrap_or_else(|| panic!("no element with id `{}`", id))
+        .dyn_into::<web_sys::HtmlSelectElement>()
+        .unwrap()
+}
+
+pub fn set_label(id: &str, text: &str) {
+    let label = document()
+        .get_element_by_id(id)
+        .unwrap_or_else(|| panic!("no element with id `{}`", id))
+        .dyn_into::<web_sys::HtmlLabelElement>()
+        .unwrap();
+    label.set_inner_text(text);
+}
+
+pub fn download_blob(filename: &str, blob: &web_sys::Blob) {
+    let url = web_sys::Url::create_object_url_with_blob(blob).unwrap();
+    let anchor = document()
+        .create_element("a")
+        .unwrap()
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .unwrap();
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+    web_sys::Url::revoke_object_url(&url).unwrap();
+}
+
+pub fn download_text(filename: &str, mime: &str, text: &str) {
+    let parts = js_sys::Array::of1(&JsValue::from_str(text));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime);
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options).unwrap();
+    download_blob(filename, &blob);
+}
+
+pub fn download_bytes(filename: &str, mime: &str, bytes: &[u8]) {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options).unwrap();
+    download_blob(filename, &blob);
+}
+
+pub async fn read_file_text(file: &web_sys::File) -> Result<String, JsValue> {
+    let text = wasm_bindgen_futures::JsFuture::from(file.text()).await?;
+    text.as_string().ok_or_else(|| JsValue::from_str("File is not text"))
+}
+
+pub async fn copy_to_clipboard(text: &str) -> Result<(), JsValue> {
+    let promise = window().navigator().clipboard().write_text(text);
+    wasm_bindgen_futures::JsFuture::from(promise).await?;
+    Ok(())
+}
+
+// The first file chosen in a file input, if any
+pub fn selected_file(id: &str) -> Option<web_sys::File> {
+    input_element(id).files().and_then(|files| files.get(0))
+}
+
+pub fn html_element(id: &str) -> web_sys::HtmlElement {
+    document()
+        .get_element_by_id(id)
+        .unwrap_or_else(|| panic!("no element with id `{}`", id))
+        .dyn_into::<web_sys::HtmlElement>()
+        .unwrap()
+}
+
+pub fn set_text(id: &str, text: &str) {
+    html_element(id).set_inner_text(text);
+}
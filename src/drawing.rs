@@ -1,6 +1,79 @@
-use web_sys::WebGl2RenderingContext;
-use nalgebra::{Rotation3, Vector3, Matrix3};
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlUniformLocation, WebGlVertexArrayObject};
+use nalgebra::{Rotation3, Vector2, Vector3, Matrix3, Matrix4};
+
+use crate::webgl_utils::{compile_shader, link_program};
+
+// Something that can draw trails, so the scene doesn't care how lines are rasterised
+pub trait TrailRenderer {
+    // Make this renderer current. `viewport` is the size in pixels of the target being drawn to
+    // and `line_width` the width of trails in those pixels
+    fn begin(&self, context: &WebGl2RenderingContext, mvp: &Matrix4<f32>, viewport: Vector2<f32>, line_width: f32);
+    fn set_colour(&self, context: &WebGl2RenderingContext, colour: &Vector3<f32>);
+    fn draw_line_strip(&self, context: &WebGl2RenderingContext, data: &[Vector3<f32>]);
+}
+
+// Plain LINE_STRIP drawing, one pixel wide on most implementations
+pub struct LineStripRenderer {
+    program: WebGlProgram,
+    buffer: WebGlBuffer,
+    vertex_array: WebGlVertexArrayObject,
+    mvp_location: Option<WebGlUniformLocation>,
+    colour_location: Option<WebGlUniformLocation>,
+}
+
+impl LineStripRenderer {
+    pub fn new(context: &WebGl2RenderingContext, vertex_shader: &str, fragment_shader: &str) -> Result<Self, String> {
+        let vert_shader = compile_shader(context, WebGl2RenderingContext::VERTEX_SHADER, vertex_shader)?;
+        let frag_shader = compile_shader(context, WebGl2RenderingContext::FRAGMENT_SHADER, fragment_shader)?;
+        let program = link_program(context, &vert_shader, &frag_shader)?;
+
+        let position_attribute_location = context.get_attrib_location(&program, "position");
+        let buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
+
+        let vertex_array = context
+            .create_vertex_array()
+            .ok_or("Could not create vertex array object")?;
+        context.bind_vertex_array(Some(&vertex_array));
+        context.vertex_attrib_pointer_with_i32(
+            position_attribute_location as u32,
+            3,
+            WebGl2RenderingContext::FLOAT,
+            true,
+            0,
+            0,
+        );
+        context.enable_vertex_attrib_array(position_attribute_location as u32);
+
+        Ok(LineStripRenderer {
+            mvp_location: context.get_uniform_location(&program, "uMVP"),
+            colour_location: context.get_uniform_location(&program, "uColour"),
+            program,
+            buffer,
+            vertex_array,
+        })
+    }
+}
+
+impl TrailRenderer for LineStripRenderer {
+    fn begin(&self, context: &WebGl2RenderingContext, mvp: &Matrix4<f32>, _viewport: Vector2<f32>, _line_width: f32) {
+        context.use_program(Some(&self.program));
+        context.bind_vertex_array(Some(&self.vertex_array));
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.buffer));
+        context.uniform_matrix4fv_with_f32_array(self.mvp_location.as_ref(), false, mvp.as_slice());
+        context.disable(WebGl2RenderingContext::BLEND);
+    }
+
+    fn set_colour(&self, context: &WebGl2RenderingContext, colour: &Vector3<f32>) {
+        context.uniform4f(self.colour_location.as_ref(), colour.x, colour.y, colour.z, 1.0);
+    }
+
+    fn draw_line_strip(&self, context: &WebGl2RenderingContext, data: &[Vector3<f32>]) {
+        draw_line_strip(context, data);
+    }
+}
 
+#[allow(dead_code)]
 pub fn draw_line(context: &WebGl2RenderingContext, start: Vector3<f32>, end: Vector3<f32>) {
     const NUM_VERTICES: usize = 2; 
 
@@ -41,6 +114,7 @@ pub fn draw_line_strip(context: &WebGl2RenderingContext, data: &[Vector3<f32>])
     context.draw_arrays(WebGl2RenderingContext::LINE_STRIP, 0, num_vertices as i32);
 }
 
+#[allow(dead_code)]
 pub fn draw_square(context: &WebGl2RenderingContext, angle: &Vector3<f32>, translator: &Vector3<f32>, scaler: &Matrix3<f32>) {
     const NUM_VERTICES: usize = 6;
     const NUM_FLOATS: usize = NUM_VERTICES * 3;
@@ -58,8 +132,8 @@ pub fn draw_square(context: &WebGl2RenderingContext, angle: &Vector3<f32>, trans
 
     let rotator = Rotation3::from_euler_angles(angle[0], angle[1], angle[2]);
 
-    for i in 0..vertex_list.len() {
-        vertex_list[i] = rotator * (scaler * vertex_list[i]) + translator;
+    for vertex in vertex_list.iter_mut() {
+        *vertex = rotator * (scaler * *vertex) + translator;
     }
 
     for i in 0..vertices.len() {
@@ -79,15 +153,16 @@ pub fn draw_square(context: &WebGl2RenderingContext, angle: &Vector3<f32>, trans
     context.draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, NUM_VERTICES as i32);
 }
 
+#[allow(dead_code)]
 pub fn draw_arrow(context: &WebGl2RenderingContext, angle: &Vector3<f32>, translator: &Vector3<f32>, scaler: &Matrix3<f32>) {
     const NUM_VERTICES: usize = 9;
     const NUM_FLOATS: usize = NUM_VERTICES * 3;
 
     let mut vertex_list : [Vector3::<f32>; NUM_VERTICES] = [
         Vector3::new(0.0, 0.0, 0.0), 
-        Vector3::new(0.0, 3.0 / 3.0, 0.0), 
+        Vector3::new(0.0, 1.0, 0.0), 
         Vector3::new(-1.0 / 6.0, 2.0 / 3.0, 0.0), 
-        Vector3::new(0.0, 3.0 / 3.0, 0.0), 
+        Vector3::new(0.0, 1.0, 0.0), 
         Vector3::new(1.0 / 6.0, 2.0 / 3.0, 0.0),
         Vector3::new(0.0, 1.0, 0.0), 
         Vector3::new(0.0, 2.0 / 3.0, -1.0 / 6.0), 
@@ -99,8 +174,8 @@ pub fn draw_arrow(context: &WebGl2RenderingContext, angle: &Vector3<f32>, transl
 
     let rotator = Rotation3::from_euler_angles(angle[0], angle[1], angle[2]);
 
-    for i in 0..vertex_list.len() {
-        vertex_list[i] = rotator * (scaler * vertex_list[i]) + translator;
+    for vertex in vertex_list.iter_mut() {
+        *vertex = rotator * (scaler * *vertex) + translator;
     }
 
     for i in 0..vertices.len() {
@@ -120,6 +195,7 @@ pub fn draw_arrow(context: &WebGl2RenderingContext, angle: &Vector3<f32>, transl
     context.draw_arrays(WebGl2RenderingContext::LINE_STRIP, 0, NUM_VERTICES as i32);
 }
 
+#[allow(dead_code)]
 pub fn draw_arrow_points(context: &WebGl2RenderingContext, start: &Vector3<f32>, end: &Vector3<f32>, clip: f32) {
     const NUM_VERTICES: usize = 9;
     const NUM_FLOATS: usize = NUM_VERTICES * 3;
@@ -138,7 +214,11 @@ pub fn draw_arrow_points(context: &WebGl2RenderingContext, start: &Vector3<f32>,
 
     let mut vertices: [f32; NUM_FLOATS] = [0.0; NUM_FLOATS];
 
-    let rotator = Rotation3::rotation_between(start, end).unwrap();
+    // No well defined rotation for opposite, zero length or non-finite vectors
+    let rotator = match Rotation3::rotation_between(start, end) {
+        Some(rotator) if rotator.matrix().iter().all(|c| c.is_finite()) => rotator,
+        _ => return,
+    };
     let mut magnitude = (end - start).magnitude();
 
     if magnitude > clip {
@@ -146,8 +226,8 @@ pub fn draw_arrow_points(context: &WebGl2RenderingContext, start: &Vector3<f32>,
     }
 
     let scaler = magnitude * Matrix3::identity();
-    for i in 0..vertex_list.len() {
-        vertex_list[i] = rotator * (scaler * vertex_list[i]) + start;
+    for vertex in vertex_list.iter_mut() {
+        *vertex = rotator * (scaler * *vertex) + start;
     }
 
     for i in 0..vertices.len() {
@@ -0,0 +1,209 @@
+use std::cell::Cell;
+
+use nalgebra::{Matrix4, Vector3};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlUniformLocation, WebGlVertexArrayObject};
+
+use crate::colour::ColourScheme;
+use crate::distributions::InitialDistribution;
+use crate::gpu_ensemble::GpuEnsemble;
+use crate::integrators::Integrator;
+use crate::simulation::is_within_bounds;
+use crate::systems::DynamicalSystem;
+use crate::webgl_utils::{compile_shader, link_program};
+
+pub const MAX_ENSEMBLE_SIZE: usize = 200_000;
+
+// A cloud of particles carried by the flow without any history
+pub struct Ensemble {
+    pub points: Vec<Vector3<f32>>,
+    pub colours: Vec<Vector3<f32>>,
+}
+
+impl Ensemble {
+    pub fn new(count: usize, distribution: &InitialDistribution, colour_scheme: &ColourScheme, seed: u32) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed as u64);
+        let points = (0..count as u64).map(|index| distribution.sample(index, &mut rng)).collect();
+        let colours = (0..count as u64).map(|index| colour_scheme.colour(index, &mut rng)).collect();
+        Ensemble { points, colours }
+    }
+
+    // Advance every particle by one step, returns how many left `bounds` and were dropped
+    pub fn step(&mut self, system: &DynamicalSystem, parameters: &[f32], integrator: Integrator, delta_t: f32, bounds: f32) -> usize {
+        let derivative = |state: Vector3<f32>| (system.derivative)(&state, parameters);
+        let count = self.points.len();
+        let mut kept = 0;
+        for i in 0..count {
+            let next = integrator.step(derivative, self.points[i], delta_t);
+            if is_within_bounds(&next, bounds) {
+                self.points[kept] = next;
+                self.colours[kept] = self.colours[i];
+                kept += 1;
+            }
+        }
+        self.points.truncate(kept);
+        self.colours.truncate(kept);
+        count - kept
+    }
+}
+
+const VERTEX_SHADER_TEXT: &str =
+r##"#version 300 es
+
+in vec3 position;
+in vec3 colour;
+uniform mat4 uMVP;
+uniform float uSize;
+uniform float uBounds;
+out vec3 vColour;
+
+void main() {
+    // Particles integrated on the GPU are kept after they diverge, move them out of view
+    if (!all(lessThanEqual(abs(position), vec3(uBounds)))) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+    gl_Position = uMVP * vec4(position, 1.0);
+    // One extra pixel for the antialiased edge
+    gl_PointSize = uSize + 1.0;
+    vColour = colour;
+}
+"##;
+
+const FRAGMENT_SHADER_TEXT: &str =
+r##"#version 300 es
+
+precision highp float;
+in vec3 vColour;
+uniform float uSize;
+out vec4 outColor;
+
+void main() {
+    // Distance in pixels from the centre of the sprite
+    float distance = length(gl_PointCoord * 2.0 - 1.0) * (uSize + 1.0) * 0.5;
+    float coverage = clamp(uSize * 0.5 + 0.5 - distance, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    outColor = vec4(vColour, coverage);
+}
+"##;
+
+// Draws an ensemble as round GL_POINTS sprites
+pub struct PointRenderer {
+    program: WebGlProgram,
+    vertex_array: WebGlVertexArrayObject,
+    position_buffer: WebGlBuffer,
+    colour_buffer: WebGlBuffer,
+    mvp_location: Option<WebGlUniformLocation>,
+    size_location: Option<WebGlUniformLocation>,
+    bounds_location: Option<WebGlUniformLocation>,
+    size: Cell<f32>,
+}
+
+impl PointRenderer {
+    pub fn new(context: &WebGl2RenderingContext) -> Result<Self, String> {
+        let vert_shader = compile_shader(context, WebGl2RenderingContext::VERTEX_SHADER, VERTEX_SHADER_TEXT)?;
+        let frag_shader = compile_shader(context, WebGl2RenderingContext::FRAGMENT_SHADER, FRAGMENT_SHADER_TEXT)?;
+        let program = link_program(context, &vert_shader, &frag_shader)?;
+
+        let position_buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        let colour_buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        let vertex_array = Self::attach(context, &program, &position_buffer, &colour_buffer)?;
+
+        Ok(PointRenderer {
+            mvp_location: context.get_uniform_location(&program, "uMVP"),
+            size_location: context.get_uniform_location(&program, "uSize"),
+            bounds_location: context.get_uniform_location(&program, "uBounds"),
+            program,
+            vertex_array,
+            position_buffer,
+            colour_buffer,
+            size: Cell::new(2.0),
+        })
+    }
+
+    fn attach(context: &WebGl2RenderingContext, program: &WebGlProgram, positions: &WebGlBuffer, colours: &WebGlBuffer) -> Result<WebGlVertexArrayObject, String> {
+        let vertex_array = context.create_vertex_array().ok_or("Could not create vertex array object")?;
+        context.bind_vertex_array(Some(&vertex_array));
+        for (name, buffer) in [("position", positions), ("colour", colours)] {
+            let location = context.get_attrib_location(program, name) as u32;
+            context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(buffer));
+            context.vertex_attrib_pointer_with_i32(location, 3, WebGl2RenderingContext::FLOAT, false, 0, 0);
+            context.enable_vertex_attrib_array(location);
+        }
+        context.bind_vertex_array(None);
+        Ok(vertex_array)
+    }
+
+    // A vertex array drawing particles straight from buffers that live on the GPU
+    pub fn vertex_array(&self, context: &WebGl2RenderingContext, positions: &WebGlBuffer, colours: &WebGlBuffer) -> Result<WebGlVertexArrayObject, String> {
+        Self::attach(context, &self.program, positions, colours)
+    }
+
+    // Diameter of the sprites in pixels of the target being drawn to
+    pub fn set_size(&self, size: f32) {
+        self.size.set(size);
+    }
+
+    pub fn draw(&self, context: &WebGl2RenderingContext, mvp: &Matrix4<f32>, ensemble: &Ensemble, bounds: f32) {
+        for (buffer, data) in [(&self.position_buffer, &ensemble.points), (&self.colour_buffer, &ensemble.colours)] {
+            let vertices: Vec<f32> = data.iter().flat_map(|vector| vector.iter().copied()).collect();
+            context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(buffer));
+            unsafe {
+                let view = js_sys::Float32Array::view(&vertices);
+                context.buffer_data_with_array_buffer_view(WebGl2RenderingContext::ARRAY_BUFFER, &view, WebGl2RenderingContext::STREAM_DRAW);
+            }
+        }
+        self.draw_vertex_array(context, mvp, &self.vertex_array, ensemble.points.len(), bounds);
+    }
+
+    pub fn draw_vertex_array(&self, context: &WebGl2RenderingContext, mvp: &Matrix4<f32>, vertex_array: &WebGlVertexArrayObject, count: usize, bounds: f32) {
+        context.use_program(Some(&self.program));
+        context.bind_vertex_array(Some(vertex_array));
+        context.uniform_matrix4fv_with_f32_array(self.mvp_location.as_ref(), false, mvp.as_slice());
+        context.uniform1f(self.size_location.as_ref(), self.size.get());
+        context.uniform1f(self.bounds_location.as_ref(), bounds);
+
+        // Soft edges would hide the particles behind them if they wrote depth
+        context.enable(WebGl2RenderingContext::BLEND);
+        context.blend_func(WebGl2RenderingContext::SRC_ALPHA, WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA);
+        context.depth_mask(false);
+        context.draw_arrays(WebGl2RenderingContext::POINTS, 0, count as i32);
+        context.depth_mask(true);
+    }
+}
+
+// A running ensemble and where it is integrated
+pub enum ActiveEnsemble {
+    Cpu(Ensemble),
+    Gpu(GpuEnsemble),
+}
+
+impl ActiveEnsemble {
+    pub fn count(&self) -> usize {
+        match self {
+            ActiveEnsemble::Cpu(ensemble) => ensemble.points.len(),
+            ActiveEnsemble::Gpu(ensemble) => ensemble.count(),
+        }
+    }
+
+    // Returns how many particles were dropped, particles on the GPU are never dropped
+    pub fn step(&mut self, system: &DynamicalSystem, parameters: &[f32], integrator: Integrator, delta_t: f32, bounds: f32) -> usize {
+        match self {
+            ActiveEnsemble::Cpu(ensemble) => ensemble.step(system, parameters, integrator, delta_t, bounds),
+            ActiveEnsemble::Gpu(ensemble) => {
+                ensemble.step(parameters, integrator, delta_t, bounds);
+                0
+            },
+        }
+    }
+
+    pub fn draw(&self, context: &WebGl2RenderingContext, points: &PointRenderer, mvp: &Matrix4<f32>, bounds: f32) {
+        match self {
+            ActiveEnsemble::Cpu(ensemble) => points.draw(context, mvp, ensemble, bounds),
+            ActiveEnsemble::Gpu(ensemble) => ensemble.draw(points, mvp, bounds),
+        }
+    }
+}
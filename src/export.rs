@@ -0,0 +1,183 @@
+use serde::Serialize;
+
+use crate::simulation::{Simulation, Trajectory};
+
+// Column order of the CSV, NPY and raw f32 exports
+pub const COLUMNS: [&str; 8] = ["id", "time", "x", "y", "z", "r", "g", "b"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Npy,
+    // Headerless little-endian f32 rows in the order of `COLUMNS`
+    RawF32,
+}
+
+impl ExportFormat {
+    pub fn parse(id: &str) -> Option<Self> {
+        match id {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            "npy" => Some(ExportFormat::Npy),
+            "f32" => Some(ExportFormat::RawF32),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Npy => "npy",
+            ExportFormat::RawF32 => "f32",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Npy | ExportFormat::RawF32 => "application/octet-stream",
+        }
+    }
+
+    pub fn export(&self, simulation: &Simulation) -> Vec<u8> {
+        match self {
+            ExportFormat::Csv => to_csv(&simulation.trajectories).into_bytes(),
+            ExportFormat::Json => to_json(simulation).into_bytes(),
+            ExportFormat::Npy => to_npy(&simulation.trajectories),
+            ExportFormat::RawF32 => rows(&simulation.trajectories).flat_map(|row| row.map(f32::to_le_bytes)).flatten().collect(),
+        }
+    }
+}
+
+fn rows(trajectories: &[Trajectory]) -> impl Iterator<Item = [f32; 8]> + '_ {
+    trajectories.iter().flat_map(|trajectory| {
+        let colour = trajectory.colour;
+        trajectory.points.iter().zip(&trajectory.times).map(move |(point, time)| {
+            [trajectory.id as f32, *time, point.x, point.y, point.z, colour.x, colour.y, colour.z]
+        })
+    })
+}
+
+fn row_count(trajectories: &[Trajectory]) -> usize {
+    trajectories.iter().map(|trajectory| trajectory.points.len()).sum()
+}
+
+pub fn to_csv(trajectories: &[Trajectory]) -> String {
+    let mut csv = COLUMNS.join(",");
+    csv.push('\n');
+    for trajectory in trajectories {
+        let colour = trajectory.colour;
+        for (point, time) in trajectory.points.iter().zip(&trajectory.times) {
+            csv.push_str(&format!("{},{},{},{},{},{},{},{}\n", trajectory.id, time, point.x, point.y, point.z, colour.x, colour.y, colour.z));
+        }
+    }
+    csv
+}
+
+#[derive(Serialize)]
+struct TrajectoryRecord<'a> {
+    id: u32,
+    colour: [f32; 3],
+    initial: [f32; 3],
+    pinned: bool,
+    times: &'a [f32],
+    points: Vec<[f32; 3]>,
+}
+
+#[derive(Serialize)]
+struct SimulationRecord<'a> {
+    system: &'a str,
+    parameters: &'a [f32],
+    time: f32,
+    trajectories: Vec<TrajectoryRecord<'a>>,
+}
+
+pub fn to_json(simulation: &Simulation) -> String {
+    let record = SimulationRecord {
+        system: simulation.system.id,
+        parameters: &simulation.parameters,
+        time: simulation.time,
+        trajectories: simulation.trajectories.iter().map(|trajectory| TrajectoryRecord {
+            id: trajectory.id,
+            colour: trajectory.colour.into(),
+            initial: trajectory.initial.into(),
+            pinned: trajectory.pinned,
+            times: &trajectory.times,
+            points: trajectory.points.iter().map(|point| (*point).into()).collect(),
+        }).collect(),
+    };
+    serde_json::to_string(&record).unwrap()
+}
+
+// NumPy .npy version 1.0 holding a float32 array of shape (points, 8)
+pub fn to_npy(trajectories: &[Trajectory]) -> Vec<u8> {
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}", row_count(trajectories), COLUMNS.len());
+    // Magic, version and length take 10 bytes, pad so the data starts on a 64 byte boundary
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut npy = b"\x93NUMPY\x01\x00".to_vec();
+    npy.extend((header.len() as u16).to_le_bytes());
+    npy.extend(header.as_bytes());
+    npy.extend(rows(trajectories).flat_map(|row| row.map(f32::to_le_bytes)).flatten());
+    npy
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::distributions::InitialDistribution;
+    use crate::systems::SYSTEMS;
+
+    fn simulation() -> Simulation {
+        let mut simulation = Simulation::new(&SYSTEMS[0], 10, InitialDistribution::cube(1.0), 0);
+        simulation.add_static(vec![Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.0, 5.0, 6.0)], vec![0.0, 0.5], Vector3::new(0.25, 0.5, 0.75));
+        simulation.add_static(vec![Vector3::new(-1.0, -2.0, -3.0)], vec![2.0], Vector3::new(1.0, 0.0, 0.0));
+        simulation
+    }
+
+    #[test]
+    fn npy_header_describes_the_rows() {
+        let simulation = simulation();
+        let npy = ExportFormat::Npy.export(&simulation);
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_length = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        let data_start = 10 + header_length;
+        assert_eq!(data_start % 64, 0);
+
+        let header = std::str::from_utf8(&npy[10..data_start]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 8), }"));
+        assert!(header.ends_with('\n'));
+
+        assert_eq!(npy.len(), data_start + 3 * 8 * 4);
+        let values: Vec<f32> = npy[data_start..].chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect();
+        let first_id = simulation.trajectories[0].id as f32;
+        assert_eq!(values[..8], [first_id, 0.0, 1.0, 2.0, 3.0, 0.25, 0.5, 0.75]);
+        assert_eq!(values[16..19], [simulation.trajectories[1].id as f32, 2.0, -1.0]);
+    }
+
+    #[test]
+    fn npy_data_matches_the_raw_export() {
+        let simulation = simulation();
+        let npy = ExportFormat::Npy.export(&simulation);
+        let raw = ExportFormat::RawF32.export(&simulation);
+        assert_eq!(raw.len(), 3 * COLUMNS.len() * 4);
+        assert!(npy.ends_with(&raw));
+    }
+
+    #[test]
+    fn npy_without_trajectories_is_empty() {
+        let npy = to_npy(&[]);
+        let header_length = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!(npy.len(), 10 + header_length);
+        assert!(std::str::from_utf8(&npy[10..]).unwrap().contains("'shape': (0, 8)"));
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+// Every frame shares a fixed 3-3-2 bit RGB palette so no per-frame quantisation is needed
+const MIN_CODE_SIZE: u8 = 8;
+const MAX_CODES: u16 = 4096;
+
+fn palette() -> Vec<u8> {
+    (0..=255u32)
+        .flat_map(|i| [((i >> 5) & 7) * 255 / 7, ((i >> 2) & 7) * 255 / 7, (i & 3) * 255 / 3])
+        .map(|channel| channel as u8)
+        .collect()
+}
+
+fn palette_index(r: u8, g: u8, b: u8) -> u8 {
+    let quantise = |channel: u8, levels: u32| (channel as u32 * (levels - 1) + 127) / 255;
+    ((quantise(r, 8) << 5) | (quantise(g, 8) << 2) | quantise(b, 4)) as u8
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    // Codes are packed least significant bit first
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = MIN_CODE_SIZE + 1;
+    let mut writer = BitWriter::default();
+    writer.write(clear, size);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        prefix = Some(match prefix {
+            None => index as u16,
+            Some(code) => match table.get(&(code, index)) {
+                Some(&extended) => extended,
+                None => {
+                    writer.write(code, size);
+                    if next == MAX_CODES {
+                        writer.write(clear, size);
+                        table.clear();
+                        next = end + 1;
+                        size = MIN_CODE_SIZE + 1;
+                    } else {
+                        // The decoder widens its codes one entry later than we add them
+                        if next >= 1 << size {
+                            size += 1;
+                        }
+                        table.insert((code, index), next);
+                        next += 1;
+                    }
+                    index as u16
+                },
+            },
+        });
+    }
+    if let Some(code) = prefix {
+        writer.write(code, size);
+    }
+    writer.write(end, size);
+    writer.finish()
+}
+
+pub struct GifEncoder {
+    bytes: Vec<u8>,
+    width: u16,
+    height: u16,
+}
+
+impl GifEncoder {
+    // An animation that loops forever
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // Global colour table of 256 entries
+        bytes.extend([0xF7, 0, 0]);
+        bytes.extend(palette());
+        bytes.extend([0x21, 0xFF, 0x0B]);
+        bytes.extend(b"NETSCAPE2.0");
+        bytes.extend([0x03, 0x01, 0x00, 0x00, 0x00]);
+        GifEncoder { bytes, width, height }
+    }
+
+    // `rgba` holds width x height pixels, top row first. `delay` is in hundredths of a second
+    pub fn add_frame(&mut self, rgba: &[u8], delay: u16) {
+        let indices: Vec<u8> = rgba.chunks_exact(4).map(|pixel| palette_index(pixel[0], pixel[1], pixel[2])).collect();
+
+        self.bytes.extend([0x21, 0xF9, 0x04, 0x00]);
+        self.bytes.extend(delay.to_le_bytes());
+        self.bytes.extend([0x00, 0x00]);
+
+        self.bytes.push(0x2C);
+        self.bytes.extend([0, 0, 0, 0]);
+        self.bytes.extend(self.width.to_le_bytes());
+        self.bytes.extend(self.height.to_le_bytes());
+        self.bytes.push(0x00);
+
+        self.bytes.push(MIN_CODE_SIZE);
+        for block in lzw_encode(&indices).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend(block);
+        }
+        self.bytes.push(0x00);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3B);
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    use super::*;
+
+    // A plain GIF LZW decoder, returns the indices and the widest code seen
+    fn lzw_decode(bytes: &[u8]) -> (Vec<u8>, u8) {
+        let clear = 1u16 << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> { (0..=end).map(|code| if code < clear { vec![code as u8] } else { Vec::new() }).collect() };
+        let mut table = reset();
+        let mut size = MIN_CODE_SIZE + 1;
+        let mut widest = size;
+        let mut previous: Option<u16> = None;
+        let mut output = Vec::new();
+        let mut position = 0;
+        loop {
+            let code = (0..size as usize).fold(0u16, |code, bit| {
+                let index = position + bit;
+                code | ((((bytes[index / 8] >> (index % 8)) & 1) as u16) << bit)
+            });
+            position += size as usize;
+            if code == clear {
+                table = reset();
+                size = MIN_CODE_SIZE + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match previous {
+                None => table[code as usize].clone(),
+                Some(previous) => {
+                    let previous = &table[previous as usize];
+                    let entry = match table.get(code as usize) {
+                        Some(entry) => entry.clone(),
+                        // The entry the encoder just added
+                        None if code as usize == table.len() => [previous.as_slice(), &previous[..1]].concat(),
+                        None => panic!("code {} is beyond the table of {}", code, table.len()),
+                    };
+                    if table.len() < MAX_CODES as usize {
+                        table.push([previous.as_slice(), &entry[..1]].concat());
+                        if table.len() == 1 << size && size < 12 {
+                            size += 1;
+                            widest = widest.max(size);
+                        }
+                    }
+                    entry
+                },
+            };
+            output.extend(&entry);
+            previous = Some(code);
+        }
+        // Nothing but padding after the end code
+        assert!(position.div_ceil(8) == bytes.len(), "{} bits used of {} bytes", position, bytes.len());
+        (output, widest)
+    }
+
+    #[test]
+    fn short_inputs_round_trip() {
+        for indices in [vec![], vec![7], vec![1, 1, 1, 1, 1, 1, 1, 1], vec![0, 255, 0, 255, 0, 255, 0]] {
+            assert_eq!(lzw_decode(&lzw_encode(&indices)).0, indices);
+        }
+    }
+
+    #[test]
+    fn long_inputs_widen_codes_and_reset_the_table() {
+        // Noise adds a table entry for nearly every code, so this fills the table several times
+        let mut rng = StdRng::seed_from_u64(1);
+        let noise: Vec<u8> = (0..40_000).map(|_| rng.gen()).collect();
+        let (decoded, widest) = lzw_decode(&lzw_encode(&noise));
+        assert_eq!(decoded, noise);
+        assert_eq!(widest, 12);
+
+        // Long runs use long entries and need far fewer codes
+        let gradient: Vec<u8> = (0..200_000u32).map(|i| (i / 97 % 256) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&gradient)).0, gradient);
+    }
+
+    #[test]
+    fn palette_round_trips_its_own_colours() {
+        let palette = palette();
+        for (index, colour) in palette.chunks_exact(3).enumerate() {
+            assert_eq!(palette_index(colour[0], colour[1], colour[2]) as usize, index);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::convert::TryInto;
+
+use nalgebra::{Matrix4, Vector3};
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlTransformFeedback, WebGlUniformLocation, WebGlVertexArrayObject};
+
+use crate::ensemble::{Ensemble, PointRenderer};
+use crate::integrators::Integrator;
+use crate::simulation::is_within_bounds;
+use crate::systems::{DynamicalSystem, MAX_PARAMETERS};
+use crate::webgl_utils::{compile_shader, link_program_with_varyings};
+
+// Largest difference relative to the size of the state accepted by `compare_with_cpu`
+pub const GPU_TOLERANCE: f32 = 1e-3;
+
+// Integrates one particle per vertex. Particles outside the bounds are left where they are, as the
+// CPU drops them
+const STEP_SHADER_TEXT: &str =
+r##"#version 300 es
+
+in vec3 state;
+uniform float uParameters[MAX_PARAMETERS];
+uniform int uIntegrator;
+uniform float uDeltaT;
+uniform float uBounds;
+out vec3 outState;
+
+vec3 derivative(vec3 s, float p[MAX_PARAMETERS]) {
+    DERIVATIVE
+}
+
+void main() {
+    vec3 s = state;
+    float h = uDeltaT;
+    if (!all(lessThanEqual(abs(s), vec3(uBounds)))) {
+        outState = s;
+        return;
+    }
+    vec3 k1 = derivative(s, uParameters);
+    if (uIntegrator == 0) {
+        outState = s + k1 * h;
+    } else if (uIntegrator == 1) {
+        outState = s + derivative(s + k1 * (h * 0.5), uParameters) * h;
+    } else {
+        vec3 k2 = derivative(s + k1 * (h * 0.5), uParameters);
+        vec3 k3 = derivative(s + k2 * (h * 0.5), uParameters);
+        vec3 k4 = derivative(s + k3 * h, uParameters);
+        outState = s + (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (h / 6.0);
+    }
+}
+"##;
+
+// Nothing is rasterised while stepping but the program still needs a fragment shader
+const DISCARD_SHADER_TEXT: &str =
+r##"#version 300 es
+
+precision highp float;
+out vec4 outColor;
+
+void main() {
+    discard;
+}
+"##;
+
+fn integrator_index(integrator: Integrator) -> i32 {
+    match integrator {
+        Integrator::Euler => 0,
+        Integrator::Midpoint => 1,
+        Integrator::Rk4 => 2,
+    }
+}
+
+fn upload(context: &WebGl2RenderingContext, buffer: &WebGlBuffer, data: &[Vector3<f32>], usage: u32) {
+    let vertices: Vec<f32> = data.iter().flat_map(|vector| vector.iter().copied()).collect();
+    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(buffer));
+    unsafe {
+        let view = js_sys::Float32Array::view(&vertices);
+        context.buffer_data_with_array_buffer_view(WebGl2RenderingContext::ARRAY_BUFFER, &view, usage);
+    }
+}
+
+// An ensemble whose particles stay on the GPU. Each step reads one position buffer and writes the
+// other through transform feedback, then they swap
+pub struct GpuEnsemble {
+    context: WebGl2RenderingContext,
+    program: WebGlProgram,
+    parameters_location: Option<WebGlUniformLocation>,
+    integrator_location: Option<WebGlUniformLocation>,
+    delta_t_location: Option<WebGlUniformLocation>,
+    bounds_location: Option<WebGlUniformLocation>,
+    transform_feedback: WebGlTransformFeedback,
+    positions: [WebGlBuffer; 2],
+    colours: WebGlBuffer,
+    step_arrays: [WebGlVertexArrayObject; 2],
+    draw_arrays: [WebGlVertexArrayObject; 2],
+    // Which position buffer holds the current state
+    current: usize,
+    count: usize,
+}
+
+impl GpuEnsemble {
+    pub fn new(context: &WebGl2RenderingContext, points: &PointRenderer, system: &DynamicalSystem, ensemble: &Ensemble) -> Result<Self, String> {
+        let source = STEP_SHADER_TEXT
+            .replace("MAX_PARAMETERS", &MAX_PARAMETERS.to_string())
+            .replace("DERIVATIVE", system.glsl);
+        let vert_shader = compile_shader(context, WebGl2RenderingContext::VERTEX_SHADER, &source)?;
+        let frag_shader = compile_shader(context, WebGl2RenderingContext::FRAGMENT_SHADER, DISCARD_SHADER_TEXT)?;
+        let program = link_program_with_varyings(context, &vert_shader, &frag_shader, &["outState"])?;
+        let transform_feedback = context.create_transform_feedback().ok_or("Could not create transform feedback")?;
+
+        let create_buffer = || context.create_buffer().ok_or("Failed to create buffer");
+        let positions = [create_buffer()?, create_buffer()?];
+        let colours = create_buffer()?;
+        for buffer in &positions {
+            upload(context, buffer, &ensemble.points, WebGl2RenderingContext::DYNAMIC_COPY);
+        }
+        upload(context, &colours, &ensemble.colours, WebGl2RenderingContext::STATIC_DRAW);
+
+        let state = context.get_attrib_location(&program, "state") as u32;
+        let step_array = |buffer: &WebGlBuffer| -> Result<WebGlVertexArrayObject, String> {
+            let vertex_array = context.create_vertex_array().ok_or("Could not create vertex array object")?;
+            context.bind_vertex_array(Some(&vertex_array));
+            context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(buffer));
+            context.vertex_attrib_pointer_with_i32(state, 3, WebGl2RenderingContext::FLOAT, false, 0, 0);
+            context.enable_vertex_attrib_array(state);
+            context.bind_vertex_array(None);
+            Ok(vertex_array)
+        };
+        let step_arrays = [step_array(&positions[0])?, step_array(&positions[1])?];
+        let draw_arrays = [points.vertex_array(context, &positions[0], &colours)?, points.vertex_array(context, &positions[1], &colours)?];
+
+        Ok(GpuEnsemble {
+            context: context.clone(),
+            parameters_location: context.get_uniform_location(&program, "uParameters"),
+            integrator_location: context.get_uniform_location(&program, "uIntegrator"),
+            delta_t_location: context.get_uniform_location(&program, "uDeltaT"),
+            bounds_location: context.get_uniform_location(&program, "uBounds"),
+            program,
+            transform_feedback,
+            positions,
+            colours,
+            step_arrays,
+            draw_arrays,
+            current: 0,
+            count: ensemble.points.len(),
+        })
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn step(&mut self, parameters: &[f32], integrator: Integrator, delta_t: f32, bounds: f32) {
+        let context = &self.context;
+        let mut padded = [0.0; MAX_PARAMETERS];
+        padded[..parameters.len()].copy_from_slice(parameters);
+        let next = 1 - self.current;
+
+        context.use_program(Some(&self.program));
+        context.uniform1fv_with_f32_array(self.parameters_location.as_ref(), &padded);
+        context.uniform1i(self.integrator_location.as_ref(), integrator_index(integrator));
+        context.uniform1f(self.delta_t_location.as_ref(), delta_t);
+        context.uniform1f(self.bounds_location.as_ref(), bounds);
+        context.bind_vertex_array(Some(&self.step_arrays[self.current]));
+        // A buffer can't be an attribute source while it is bound for feedback
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, None);
+        context.bind_transform_feedback(WebGl2RenderingContext::TRANSFORM_FEEDBACK, Some(&self.transform_feedback));
+        context.bind_buffer_base(WebGl2RenderingContext::TRANSFORM_FEEDBACK_BUFFER, 0, Some(&self.positions[next]));
+
+        context.enable(WebGl2RenderingContext::RASTERIZER_DISCARD);
+        context.begin_transform_feedback(WebGl2RenderingContext::POINTS);
+        context.draw_arrays(WebGl2RenderingContext::POINTS, 0, self.count as i32);
+        context.end_transform_feedback();
+        context.disable(WebGl2RenderingContext::RASTERIZER_DISCARD);
+
+        context.bind_buffer_base(WebGl2RenderingContext::TRANSFORM_FEEDBACK_BUFFER, 0, None);
+        context.bind_transform_feedback(WebGl2RenderingContext::TRANSFORM_FEEDBACK, None);
+        context.bind_vertex_array(None);
+        self.current = next;
+    }
+
+    pub fn draw(&self, points: &PointRenderer, mvp: &Matrix4<f32>, bounds: f32) {
+        points.draw_vertex_array(&self.context, mvp, &self.draw_arrays[self.current], self.count, bounds);
+    }
+
+    // Copy the current positions back to the CPU, this stalls until the GPU has caught up
+    pub fn read_back(&self) -> Vec<Vector3<f32>> {
+        let mut bytes = vec![0u8; self.count * 12];
+        self.context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.positions[self.current]));
+        self.context.get_buffer_sub_data_with_i32_and_u8_array(WebGl2RenderingContext::ARRAY_BUFFER, 0, &mut bytes);
+        bytes
+            .chunks_exact(12)
+            .map(|chunk| Vector3::from_fn(|i, _| f32::from_le_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap())))
+            .collect()
+    }
+}
+
+impl Drop for GpuEnsemble {
+    fn drop(&mut self) {
+        let context = &self.context;
+        for vertex_array in self.step_arrays.iter().chain(&self.draw_arrays) {
+            context.delete_vertex_array(Some(vertex_array));
+        }
+        for buffer in self.positions.iter().chain([&self.colours]) {
+            context.delete_buffer(Some(buffer));
+        }
+        context.delete_transform_feedback(Some(&self.transform_feedback));
+        context.delete_program(Some(&self.program));
+    }
+}
+
+// The CPU reference for the step shader: particles outside `bounds` stay where they are instead
+// of being dropped, so they line up with the GPU buffer
+pub fn step_reference(system: &DynamicalSystem, parameters: &[f32], integrator: Integrator, delta_t: f32, bounds: f32, points: &mut [Vector3<f32>], steps: usize) {
+    let derivative = |state: Vector3<f32>| (system.derivative)(&state, parameters);
+    for _ in 0..steps {
+        for point in points.iter_mut().filter(|point| is_within_bounds(point, bounds)) {
+            *point = integrator.step(derivative, *point, delta_t);
+        }
+    }
+}
+
+// The largest difference between matching particles relative to the size of the state, ignoring
+// particles that diverged on both
+pub fn largest_difference(gpu: &[Vector3<f32>], cpu: &[Vector3<f32>], bounds: f32) -> Result<f32, String> {
+    if gpu.len() != cpu.len() {
+        return Err(format!("the GPU has {} particles but the CPU has {}", gpu.len(), cpu.len()));
+    }
+    let mut largest: f32 = 0.0;
+    for (gpu, cpu) in gpu.iter().zip(cpu) {
+        if is_within_bounds(cpu, bounds) && is_within_bounds(gpu, bounds) {
+            largest = largest.max((gpu - cpu).norm() / cpu.norm().max(1.0));
+        } else if is_within_bounds(cpu, bounds) != is_within_bounds(gpu, bounds) {
+            return Err(String::from("a particle diverged on only one of the CPU and GPU"));
+        }
+    }
+    Ok(largest)
+}
+
+// Step the same particles on the CPU, which is the reference, and on the GPU. Returns the largest
+// difference between them relative to the size of the state, ignoring particles that diverged
+#[allow(clippy::too_many_arguments)]
+pub fn compare_with_cpu(context: &WebGl2RenderingContext, points: &PointRenderer, system: &DynamicalSystem, parameters: &[f32], integrator: Integrator, delta_t: f32, bounds: f32, ensemble: &Ensemble, steps: usize) -> Result<f32, String> {
+    let mut gpu = GpuEnsemble::new(context, points, system, ensemble)?;
+    for _ in 0..steps {
+        gpu.step(parameters, integrator, delta_t, bounds);
+    }
+    let mut cpu = ensemble.points.clone();
+    step_reference(system, parameters, integrator, delta_t, bounds, &mut cpu, steps);
+    largest_difference(&gpu.read_back(), &cpu, bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::find_system;
+
+    fn lorenz() -> &'static DynamicalSystem {
+        find_system("lorenz").unwrap()
+    }
+
+    #[test]
+    fn reference_matches_ensemble_step_inside_bounds() {
+        let system = lorenz();
+        let parameters = system.defaults();
+        let start = vec![Vector3::new(1.0, 1.0, 1.0), Vector3::new(-5.0, 3.0, 20.0)];
+        let mut reference = start.clone();
+        step_reference(system, &parameters, Integrator::Rk4, 0.01, 1000.0, &mut reference, 50);
+
+        let mut ensemble = Ensemble { points: start, colours: vec![Vector3::zeros(); 2] };
+        for _ in 0..50 {
+            assert_eq!(ensemble.step(system, &parameters, Integrator::Rk4, 0.01, 1000.0), 0);
+        }
+        assert_eq!(reference, ensemble.points);
+    }
+
+    #[test]
+    fn reference_freezes_particles_outside_bounds() {
+        let system = lorenz();
+        let outside = Vector3::new(50.0, 0.0, 0.0);
+        let mut points = vec![outside, Vector3::new(f32::NAN, 0.0, 0.0)];
+        step_reference(system, &system.defaults(), Integrator::Euler, 0.01, 10.0, &mut points, 10);
+        assert_eq!(points[0], outside);
+        assert!(points[1].x.is_nan());
+    }
+
+    #[test]
+    fn difference_is_relative_and_skips_diverged_particles() {
+        let cpu = [Vector3::new(100.0, 0.0, 0.0), Vector3::new(500.0, 0.0, 0.0)];
+        let gpu = [Vector3::new(100.1, 0.0, 0.0), Vector3::new(900.0, 0.0, 0.0)];
+        let difference = largest_difference(&gpu, &cpu, 200.0).unwrap();
+        assert!((difference - 1e-3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn difference_rejects_particles_diverging_on_one_side() {
+        let cpu = [Vector3::new(1.0, 0.0, 0.0)];
+        let gpu = [Vector3::new(f32::INFINITY, 0.0, 0.0)];
+        assert_eq!(largest_difference(&gpu, &cpu, 200.0), Err(String::from("a particle diverged on only one of the CPU and GPU")));
+        assert!(largest_difference(&gpu, &[], 200.0).is_err());
+    }
+}
+
+// Run with `wasm-pack test --headless --chrome` (or --firefox), these need a WebGL 2 context
+#[cfg(all(test, target_arch = "wasm32"))]
+mod browser_tests {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+    use web_sys::HtmlCanvasElement;
+
+    use super::*;
+    use crate::colour::ColourScheme;
+    use crate::distributions::InitialDistribution;
+    use crate::systems::SYSTEMS;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn context() -> WebGl2RenderingContext {
+        let canvas: HtmlCanvasElement = web_sys::window().unwrap().document().unwrap().create_element("canvas").unwrap().dyn_into().unwrap();
+        canvas.get_context("webgl2").unwrap().unwrap().dyn_into().unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn step_shader_matches_cpu_for_every_system_and_integrator() {
+        let context = context();
+        let points = PointRenderer::new(&context).unwrap();
+        for system in SYSTEMS {
+            let ensemble = Ensemble::new(256, &InitialDistribution::cube(system.spawn_range), &ColourScheme::Random, 1);
+            for integrator in [Integrator::Euler, Integrator::Midpoint, Integrator::Rk4] {
+                let difference = compare_with_cpu(&context, &points, system, &system.defaults(), integrator, 0.001, 1000.0, &ensemble, 20).unwrap();
+                assert!(difference <= GPU_TOLERANCE, "{} with {:?}: {}", system.name, integrator, difference);
+            }
+        }
+    }
+}
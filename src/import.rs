@@ -0,0 +1,239 @@
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+// A precomputed trajectory to display as a static trail
+pub struct ImportedTrail {
+    pub points: Vec<Vector3<f32>>,
+    pub times: Vec<f32>,
+    pub colour: Option<Vector3<f32>>,
+}
+
+pub enum Imported {
+    InitialConditions(Vec<Vector3<f32>>),
+    Trails(Vec<ImportedTrail>),
+}
+
+impl Imported {
+    pub fn describe(&self) -> String {
+        match self {
+            Imported::InitialConditions(points) => format!("imported {} initial conditions", points.len()),
+            Imported::Trails(trails) => format!("imported {} trajectories", trails.len()),
+        }
+    }
+}
+
+// JSON files and anything that looks like JSON are read as JSON, everything else as CSV
+pub fn parse_import(filename: &str, text: &str) -> Result<Imported, String> {
+    let trimmed = text.trim_start();
+    if filename.to_lowercase().ends_with(".json") || trimmed.starts_with('{') || trimmed.starts_with('[') {
+        parse_json(text)
+    } else {
+        parse_csv(text)
+    }
+}
+
+fn check_finite(point: &[f32; 3], context: &str) -> Result<Vector3<f32>, String> {
+    if point.iter().all(|c| c.is_finite()) {
+        Ok(Vector3::from(*point))
+    } else {
+        Err(format!("{}: coordinates must be finite", context))
+    }
+}
+
+// Trails without times are numbered by point
+fn trail(points: Vec<Vector3<f32>>, times: Option<Vec<f32>>, colour: Option<Vector3<f32>>, context: &str) -> Result<ImportedTrail, String> {
+    if points.is_empty() {
+        return Err(format!("{}: has no points", context));
+    }
+    let times = match times {
+        Some(times) if times.len() != points.len() => return Err(format!("{}: has {} points but {} times", context, points.len(), times.len())),
+        Some(times) => times,
+        None => (0..points.len()).map(|i| i as f32).collect(),
+    };
+    Ok(ImportedTrail { points, times, colour })
+}
+
+#[derive(Deserialize)]
+struct TrailRecord {
+    points: Vec<[f32; 3]>,
+    times: Option<Vec<f32>>,
+    colour: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonImport {
+    // A bare list of initial conditions
+    Points(Vec<[f32; 3]>),
+    InitialConditions { initial_conditions: Vec<[f32; 3]> },
+    // The layout written by the JSON trajectory export
+    Trails { trajectories: Vec<TrailRecord> },
+}
+
+fn parse_json(text: &str) -> Result<Imported, String> {
+    let parsed: JsonImport = serde_json::from_str(text).map_err(|_| {
+        String::from("JSON must be a list of [x, y, z] points, an object with \"initial_conditions\" or an object with \"trajectories\"")
+    })?;
+    match parsed {
+        JsonImport::Points(points) | JsonImport::InitialConditions { initial_conditions: points } => points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| check_finite(point, &format!("point {}", i + 1)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Imported::InitialConditions),
+        JsonImport::Trails { trajectories } => trajectories
+            .into_iter()
+            .enumerate()
+            .map(|(i, record)| {
+                let context = format!("trajectory {}", i + 1);
+                let points = record.points.iter().map(|point| check_finite(point, &context)).collect::<Result<Vec<_>, _>>()?;
+                trail(points, record.times, record.colour.map(Vector3::from), &context)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Imported::Trails),
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Imported, String> {
+    let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#')).peekable();
+    let first = lines.peek().ok_or("The file is empty")?.1;
+
+    // Without a header the columns are x, y, z
+    let has_header = first.split(',').any(|cell| cell.trim().parse::<f32>().is_err());
+    let columns: Vec<String> = if has_header {
+        lines.next().unwrap().1.split(',').map(|cell| cell.trim().to_lowercase()).collect()
+    } else {
+        vec![String::from("x"), String::from("y"), String::from("z")]
+    };
+    let column = |name: &str| columns.iter().position(|column| column == name);
+    let (x, y, z) = match (column("x"), column("y"), column("z")) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Err(String::from("The header needs x, y and z columns")),
+    };
+    let id = column("id");
+    let time = column("time");
+    let colour = match (column("r"), column("g"), column("b")) {
+        (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+        _ => None,
+    };
+
+    let mut rows = Vec::new();
+    for (index, line) in lines {
+        let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+        if cells.len() != columns.len() {
+            return Err(format!("line {}: expected {} values, found {}", index + 1, columns.len(), cells.len()));
+        }
+        let values = cells
+            .iter()
+            .zip(&columns)
+            .map(|(cell, name)| cell.parse::<f32>().ok().filter(|value| value.is_finite())
+                .ok_or_else(|| format!("line {}: {} is not a number in column {}", index + 1, cell, name)))
+            .collect::<Result<Vec<f32>, String>>()?;
+        rows.push(values);
+    }
+    if rows.is_empty() {
+        return Err(String::from("The file has no data rows"));
+    }
+
+    let point = |row: &Vec<f32>| Vector3::new(row[x], row[y], row[z]);
+    let id = match id {
+        Some(id) => id,
+        None => return Ok(Imported::InitialConditions(rows.iter().map(point).collect())),
+    };
+
+    // Rows sharing an id form one trail, in the order they appear
+    let mut ids: Vec<f32> = Vec::new();
+    let mut grouped: Vec<Vec<&Vec<f32>>> = Vec::new();
+    for row in &rows {
+        match ids.iter().position(|existing| *existing == row[id]) {
+            Some(i) => grouped[i].push(row),
+            None => {
+                ids.push(row[id]);
+                grouped.push(vec![row]);
+            },
+        }
+    }
+    grouped
+        .into_iter()
+        .zip(ids)
+        .map(|(rows, id)| {
+            let points = rows.iter().map(|row| point(row)).collect();
+            let times = time.map(|time| rows.iter().map(|row| row[time]).collect());
+            let colour = colour.map(|(r, g, b)| Vector3::new(rows[0][r], rows[0][g], rows[0][b]));
+            trail(points, times, colour, &format!("trajectory {}", id))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Imported::Trails)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(filename: &str, text: &str) -> String {
+        match parse_import(filename, text) {
+            Ok(imported) => panic!("expected an error, got {}", imported.describe()),
+            Err(e) => e,
+        }
+    }
+
+    fn trails(text: &str) -> Vec<ImportedTrail> {
+        match parse_import("trails.csv", text) {
+            Ok(Imported::Trails(trails)) => trails,
+            Ok(imported) => panic!("expected trails, got {}", imported.describe()),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn empty_files_are_rejected() {
+        assert_eq!(error("empty.csv", ""), "The file is empty");
+        assert_eq!(error("empty.csv", "\n# only a comment\n  \n"), "The file is empty");
+        assert_eq!(error("header.csv", "x,y,z\n"), "The file has no data rows");
+    }
+
+    #[test]
+    fn missing_columns_are_rejected() {
+        assert_eq!(error("points.csv", "x,y,time\n1,2,3\n"), "The header needs x, y and z columns");
+        assert_eq!(error("points.csv", "x,y,z\n1,2,3\n4,5\n"), "line 3: expected 3 values, found 2");
+    }
+
+    #[test]
+    fn non_numeric_fields_are_rejected() {
+        assert_eq!(error("points.csv", "x,y,z\n1,2,3\n4,five,6\n"), "line 3: five is not a number in column y");
+        assert_eq!(error("points.csv", "id,x,y,z\n1,2,3,inf\n"), "line 2: inf is not a number in column z");
+    }
+
+    #[test]
+    fn headerless_rows_are_initial_conditions() {
+        match parse_import("points.csv", "1, 2, 3\n# comment\n4,5,6\n") {
+            Ok(Imported::InitialConditions(points)) => assert_eq!(points, vec![Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.0, 5.0, 6.0)]),
+            _ => panic!("expected initial conditions"),
+        }
+    }
+
+    #[test]
+    fn rows_are_grouped_by_id_in_order_of_appearance() {
+        let trails = trails("id,time,x,y,z,r,g,b\n2,0,1,1,1,1,0,0\n5,0,9,9,9,0,1,0\n2,1,2,2,2,1,0,0\n5,1,8,8,8,0,1,0\n2,2,3,3,3,1,0,0\n");
+        assert_eq!(trails.len(), 2);
+        assert_eq!(trails[0].points, vec![Vector3::new(1.0, 1.0, 1.0), Vector3::new(2.0, 2.0, 2.0), Vector3::new(3.0, 3.0, 3.0)]);
+        assert_eq!(trails[0].times, vec![0.0, 1.0, 2.0]);
+        assert_eq!(trails[0].colour, Some(Vector3::new(1.0, 0.0, 0.0)));
+        assert_eq!(trails[1].points, vec![Vector3::new(9.0, 9.0, 9.0), Vector3::new(8.0, 8.0, 8.0)]);
+        assert_eq!(trails[1].colour, Some(Vector3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn trails_without_times_are_numbered() {
+        let trails = trails("x,y,z,id\n0,0,0,1\n1,1,1,1\n");
+        assert_eq!(trails[0].times, vec![0.0, 1.0]);
+        assert_eq!(trails[0].colour, None);
+    }
+
+    #[test]
+    fn json_errors_name_the_trajectory() {
+        assert_eq!(error("trails.json", r#"{"trajectories": [{"points": [[0, 0, 0]]}, {"points": [[1, 2, 3]], "times": [0, 1]}]}"#), "trajectory 2: has 1 points but 2 times");
+        assert_eq!(error("trails.json", r#"{"trajectories": [{"points": []}]}"#), "trajectory 1: has no points");
+        assert!(error("trails.json", r#"{"points": 3}"#).starts_with("JSON must be"));
+    }
+}
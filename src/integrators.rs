@@ -0,0 +1,50 @@
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    Euler,
+    Midpoint,
+    Rk4,
+}
+
+pub const INTEGRATORS: [Integrator; 3] = [Integrator::Euler, Integrator::Midpoint, Integrator::Rk4];
+
+impl Integrator {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Integrator::Euler => "euler",
+            Integrator::Midpoint => "midpoint",
+            Integrator::Rk4 => "rk4",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::Euler => "Euler",
+            Integrator::Midpoint => "midpoint (RK2)",
+            Integrator::Rk4 => "Runge-Kutta (RK4)",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        INTEGRATORS.iter().find(|integrator| integrator.id() == id).copied()
+    }
+
+    pub fn step<F: Fn(Vector3<f32>) -> Vector3<f32>>(&self, derivative: F, state: Vector3<f32>, delta_t: f32) -> Vector3<f32> {
+        match self {
+            Integrator::Euler => state + derivative(state) * delta_t,
+            Integrator::Midpoint => {
+                let k1 = derivative(state);
+                state + derivative(state + k1 * (delta_t * 0.5)) * delta_t
+            },
+            Integrator::Rk4 => {
+                let k1 = derivative(state);
+                let k2 = derivative(state + k1 * (delta_t * 0.5));
+                let k3 = derivative(state + k2 * (delta_t * 0.5));
+                let k4 = derivative(state + k3 * delta_t);
+                state + (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (delta_t / 6.0)
+            },
+        }
+    }
+}
@@ -1,41 +1,91 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
-use futures_util::{future::ready, stream::StreamExt};
 use web_sys::WebGl2RenderingContext;
-use nalgebra::{Vector3, Matrix4, Point3};
-use console_error_panic_hook;
+use nalgebra::{Vector2, Vector3, Matrix4};
 
 use gloo::events::{EventListenerOptions, EventListener};
 use gloo::console::log;
-use gloo::timers::future::IntervalStream;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::sync::Arc;
 
-use rand::random;
-
-use crate::dom::{request_animation_frame, document, body, window};
-use crate::webgl_utils::{compile_shader, clear, link_program};
-use crate::drawing::{draw_line_strip, draw_line};
+use crate::dom::{request_animation_frame, document, body, window, input_element, select_element, html_element, set_label, set_text, download_text, download_bytes, read_file_text, selected_file, copy_to_clipboard};
+use crate::webgl_utils::{clear, resize_canvas};
+use crate::drawing::{TrailRenderer, LineStripRenderer};
+use crate::thick_lines::ThickLineRenderer;
+use crate::shading::{ShadedTrailRenderer, TrailStyle};
+use crate::ensemble::{ActiveEnsemble, Ensemble, PointRenderer, MAX_ENSEMBLE_SIZE};
+use crate::worker::SimulationWorker;
+use crate::density::{DensityHistogram, Plane};
+use crate::gpu_ensemble::{GpuEnsemble, compare_with_cpu, GPU_TOLERANCE};
+use crate::camera::{Camera, MIN_FOV, MAX_FOV};
+use crate::bookmarks::{Bookmark, presets, replace_presets, home_pose, bookmark_select, refresh_bookmark_select};
+use crate::simulation::{Simulation, Trajectory};
+use crate::picking::{SpawnTarget, to_ndc, intersect, nearest_trajectory};
+use crate::colour::{to_hex, from_hex, read_colour_scheme, show_colour_scheme};
+use crate::distributions::{InitialDistribution, read_distribution, show_distribution, show_distribution_parameters};
+use crate::settings::{Settings, read_settings, show_settings, populate_integrator_select, populate_trail_style_select};
+use crate::camera_path::{CameraPath, PathPlayback};
+use crate::systems::{DynamicalSystem, SYSTEMS, find_system};
+use crate::parameter_controls::{build_parameter_controls, populate_system_select, show_parameter};
+use crate::presets::{Preset, builtin_presets, load_user_presets, save_user_presets, presets_to_json, presets_from_json, refresh_preset_select};
+use crate::scene::Scene;
+use crate::export::ExportFormat;
+use crate::import::{Imported, parse_import};
+use crate::mesh::Mesh;
+use crate::svg::SvgWriter;
+use crate::recording::Recording;
+use crate::screenshot::{render_offscreen, save_png};
+use crate::sweep::{ParameterSweep, populate_sweep_parameters, show_sweep_range, read_sweep};
 
+mod bookmarks;
+mod camera;
+mod camera_path;
+mod colour;
+mod density;
+mod distributions;
 mod dom;
 mod drawing;
+mod ensemble;
+mod integrators;
+mod parameter_controls;
+mod export;
+mod gif;
+mod gpu_ensemble;
+mod import;
+mod mesh;
+mod picking;
+mod presets;
+mod scene;
+mod recording;
+mod screenshot;
+mod settings;
+mod shading;
+mod simulation;
+mod svg;
+mod sweep;
+mod systems;
+mod thick_lines;
 mod webgl_utils;
+mod worker;
 
 // Constants
-const SENSITIVITY: f32 = 0.1;
-const BOUNDS: f32 = 10000.0;
-const DELTA_T: f32 = 0.002;
-const CAMERA_SPEED: f32 = 2.0;
 const CAMERA_ROTATION: f32 = 0.5;
-const MAX_POINTS: usize = 500;
-const NUM_TRAJECTORIES: usize = 20;
-const RANDOM_RANGE: f32 = 100.0;
-const SPAWN_INTERVAL: u32 = 5_00;
-const ORIGINAL_CAMERA_POS: Vector3<f32> = Vector3::new(0.0, 0.0, 500.0);
+const ZOOM_SENSITIVITY: f32 = 0.001;
+// Default gap in seconds between recorded camera keyframes
+const PATH_KEYFRAME_SPACING: f32 = 2.0;
+// How close in pixels a click has to be to a trail to select it
+const PICK_RADIUS: f32 = 10.0;
+const HIGHLIGHT_COLOUR: Vector3<f32> = Vector3::new(1.0, 0.9, 0.2);
+// Size of the marker drawn at the head of the selected trajectory
+const HIGHLIGHT_MARKER_SIZE: f32 = 1.0;
+// Frames between redraws of the density heatmap
+const DENSITY_REDRAW_FRAMES: u64 = 15;
+// How long in milliseconds the instability warning stays up after a trajectory diverges
+const INSTABILITY_WARNING_DURATION: f64 = 3000.0;
 
-const VERTEX_SHADER_TEXT: &'static str = 
+const VERTEX_SHADER_TEXT: &str = 
 r##"#version 300 es
 
 in vec4 position;
@@ -43,11 +93,10 @@ uniform mat4 uMVP;
 
 void main() {
     gl_Position = uMVP * position;
-    gl_PointSize = 100.0;
 }
 "##;
 
-const FRAGMENT_SHADER_TEXT: &'static str =
+const FRAGMENT_SHADER_TEXT: &str =
 r##"#version 300 es
 
 precision highp float;
@@ -59,37 +108,188 @@ void main() {
 }
 "##;
 
-fn lorentz(state: Vector3<f32>, sigma: f32, rho: f32, beta: f32) -> Vector3<f32> {
-   Vector3::new(
-        sigma * (state.y - state.x), 
-        state.x * (rho - state.z) - state.y,
-        state.x * state.y - beta * state.z,
-    )
-}
-
 fn toggle_pause(paused: &Arc<RefCell<bool>>) {
     let pause_button = document().get_element_by_id("pause-button").unwrap();
     let pause_button: web_sys::HtmlButtonElement = pause_button.dyn_into::<web_sys::HtmlButtonElement>().unwrap();
     let paused_local = *paused.borrow();
     if paused_local {
         pause_button.set_inner_text("pause");
-        *paused.borrow_mut() = false.into();
+        *paused.borrow_mut() = false;
     } else {
         pause_button.set_inner_text("resume");
-        *paused.borrow_mut() = true.into();
+        *paused.borrow_mut() = true;
+    }
+}
+
+fn system_home(system: &DynamicalSystem) -> Vector3<f32> {
+    Vector3::new(0.0, 0.0, system.camera_distance)
+}
+
+fn current_scene(simulation: &Simulation, settings: &Settings, camera: &Camera) -> Scene {
+    Scene {
+        system: simulation.system,
+        parameters: simulation.parameters.clone(),
+        integrator: settings.integrator,
+        delta_t: settings.delta_t,
+        seed: simulation.seed,
+        camera: camera.pose(),
+        colour_scheme: simulation.colour_scheme,
+        max_points: settings.max_points,
     }
 }
 
-fn spawn_random_trajectory(data: &mut Vec<Vec<Vector3<f32>>>, colours: &mut Vec<Vector3<f32>>) {
-    let max = data.len();
-    data.push(Vec::default());
-    data[max].push(Vector3::new(RANDOM_RANGE * (random::<f32>() - 0.5), RANDOM_RANGE * (random::<f32>() - 0.5), RANDOM_RANGE * (random::<f32>() - 0.5)));
-    colours.push(Vector3::new(random(), random(), random()));
+fn apply_scene(scene: &Scene, simulation: &mut Simulation, settings: &mut Settings, camera: &mut Camera) -> Result<(), String> {
+    let new_settings = Settings {
+        integrator: scene.integrator,
+        delta_t: scene.delta_t,
+        seed: scene.seed,
+        max_points: scene.max_points,
+        random_range: scene.system.spawn_range,
+        ..*settings
+    };
+    new_settings.validate()?;
+    *settings = new_settings;
+    simulation.set_system(scene.system);
+    simulation.parameters = scene.parameters.clone();
+    simulation.colour_scheme = scene.colour_scheme;
+    simulation.reseed(scene.seed);
+    simulation.distribution = InitialDistribution::cube(settings.random_range);
+    camera.set_pose(&scene.camera);
+    Ok(())
+}
+
+// `viewport` is the size in pixels of the target and `line_width` the trail width in those pixels
+// When there is an ensemble its particles are drawn instead of the trails
+#[allow(clippy::too_many_arguments)]
+fn draw_scene(context: &WebGl2RenderingContext, renderer: &dyn TrailRenderer, points: &PointRenderer, mvp: &Matrix4<f32>, viewport: Vector2<f32>, line_width: f32, simulation: &Simulation, ensemble: Option<&ActiveEnsemble>, selected: Option<u32>, bounds: f32) {
+    clear(context);
+    if let Some(ensemble) = ensemble {
+        ensemble.draw(context, points, mvp, bounds);
+    }
+    renderer.begin(context, mvp, viewport, line_width);
+
+    // draw trajectories
+    if ensemble.is_none() {
+        for trajectory in simulation.trajectories.iter() {
+            renderer.set_colour(context, &trajectory.colour);
+            renderer.draw_line_strip(context, trajectory.points.as_slice());
+        }
+    }
+
+    // highlight the selected trajectory
+    if let Some(trajectory) = selected.and_then(|id| simulation.get(id)) {
+        let head = trajectory.head();
+        renderer.set_colour(context, &HIGHLIGHT_COLOUR);
+        renderer.draw_line_strip(context, trajectory.points.as_slice());
+        for axis in 0..3 {
+            let mut offset = Vector3::zeros();
+            offset[axis] = HIGHLIGHT_MARKER_SIZE;
+            renderer.draw_line_strip(context, &[head - offset, head + offset]);
+        }
+    }
+
+    // draw axes
+    renderer.set_colour(context, &Vector3::new(1.0, 1.0, 1.0));
+    renderer.draw_line_strip(context, &[Vector3::new(-bounds, 0.0, 0.0), Vector3::new(bounds, 0.0, 0.0)]);
+    renderer.draw_line_strip(context, &[Vector3::new(0.0, -bounds, 0.0), Vector3::new(0.0, bounds, 0.0)]);
+    renderer.draw_line_strip(context, &[Vector3::new(0.0, 0.0, -bounds), Vector3::new(0.0, 0.0, bounds)]);
+}
+
+// Spawn imported initial conditions or add imported trails, reporting the outcome in the import label
+fn import_file(file: web_sys::File, simulation: Arc<RefCell<Simulation>>) {
+    spawn_local(async move {
+        let imported = read_file_text(&file)
+            .await
+            .map_err(|e| format!("Could not read file: {:?}", e))
+            .and_then(|text| parse_import(&file.name(), &text));
+        match imported {
+            Ok(imported) => {
+                let mut simulation = simulation.borrow_mut();
+                let mut label = format!("{} from {}", imported.describe(), file.name());
+                match &imported {
+                    Imported::InitialConditions(points) => {
+                        let ids: Vec<u32> = points.iter().map(|point| simulation.spawn_at(*point)).collect();
+                        // Spawning evicts the oldest trajectories once the limit is reached, including imported ones
+                        let kept = ids.iter().filter(|id| simulation.get(**id).is_some()).count();
+                        if kept < ids.len() {
+                            label.push_str(&format!(", kept the last {}, raise the maximum trajectories setting to keep more", kept));
+                        }
+                    },
+                    Imported::Trails(trails) => {
+                        for trail in trails {
+                            let colour = trail.colour.unwrap_or_else(|| simulation.next_colour());
+                            simulation.add_static(trail.points.clone(), trail.times.clone(), colour);
+                        }
+                    },
+                }
+                set_label("import-label", &label);
+            },
+            Err(e) => set_label("import-label", &format!("{}: {}", file.name(), e)),
+        }
+    });
+}
+
+fn toggle_turntable(camera: &mut Camera, simulation: &Simulation) {
+    if camera.turntable.is_some() {
+        camera.take_control();
+    } else {
+        camera.start_turntable(simulation.centroid());
+    }
+}
+
+fn show_path(path: &CameraPath) {
+    set_label("path-label", &format!("{} keyframes, {:.1} s", path.keyframes.len(), path.duration()));
+}
+
+fn select_trajectory(selected: &mut Option<u32>, simulation: &Simulation, id: Option<u32>) {
+    *selected = id.filter(|id| simulation.get(*id).is_some());
+    match selected.and_then(|id| simulation.get(id)) {
+        Some(trajectory) => {
+            html_element("inspector").set_hidden(false);
+            set_text("inspector-title", &format!("trajectory #{}", trajectory.id));
+            input_element("inspector-colour").set_value(&to_hex(&trajectory.colour));
+            input_element("inspector-pin").set_checked(trajectory.pinned);
+            show_inspector(trajectory, simulation.time);
+        },
+        None => html_element("inspector").set_hidden(true),
+    }
+}
+
+fn show_inspector(trajectory: &Trajectory, time: f32) {
+    let vector = |v: &Vector3<f32>| format!("({:.3}, {:.3}, {:.3})", v.x, v.y, v.z);
+    set_text("inspector-details", &format!(
+        "initial condition: {}\ncurrent state: {}\nspawned at: t = {:.3}\nage: {:.3} ({} steps)\nlocal Lyapunov estimate: {:.3}\nwing switches: {}",
+        vector(&trajectory.initial),
+        vector(&trajectory.head()),
+        trajectory.spawn_time,
+        trajectory.age(time),
+        trajectory.steps,
+        trajectory.local_lyapunov,
+        trajectory.wing_switches,
+    ));
+}
+
+fn follow_trajectory(camera: &mut Camera, id: Option<u32>) {
+    camera.take_control();
+    camera.follow = id;
+    let text = match id {
+        Some(id) => {
+            input_element("follow-id").set_value(&id.to_string());
+            format!("following #{}", id)
+        },
+        None => "not following".to_string(),
+    };
+    set_label("follow-id-label", &text);
 }
 
 #[wasm_bindgen(start)]
 fn start() -> Result<(), JsValue> {
 
+    // The simulation worker loads this module too but has no page to set up
+    if js_sys::global().dyn_into::<web_sys::Window>().is_err() {
+        return Ok(());
+    }
+
     console_error_panic_hook::set_once();
     let doc= document();
     let canvas = doc.get_element_by_id("canvas").unwrap();
@@ -99,134 +299,442 @@ fn start() -> Result<(), JsValue> {
     let pause_button = doc.get_element_by_id("pause-button").unwrap();
     let pause_button: web_sys::HtmlButtonElement = pause_button.dyn_into::<web_sys::HtmlButtonElement>()?;
 
-    let rho_slider = doc.get_element_by_id("rho-slider").unwrap();
-    let rho_slider: web_sys::HtmlInputElement = rho_slider.dyn_into::<web_sys::HtmlInputElement>()?;
-
-    let beta_slider = doc.get_element_by_id("beta-slider").unwrap();
-    let beta_slider: web_sys::HtmlInputElement = beta_slider.dyn_into::<web_sys::HtmlInputElement>()?;
-
-    let sigma_slider = doc.get_element_by_id("sigma-slider").unwrap();
-    let sigma_slider: web_sys::HtmlInputElement = sigma_slider.dyn_into::<web_sys::HtmlInputElement>()?;
-
     let paused: Arc<RefCell<bool>> = RefCell::new(false).into();
-    let rho: Arc<RefCell<f32>> = RefCell::new(rho_slider.value().parse().unwrap()).into();
-    let beta: Arc<RefCell<f32>> = RefCell::new(beta_slider.value().parse().unwrap()).into();
-    let sigma: Arc<RefCell<f32>> = RefCell::new(beta_slider.value().parse().unwrap()).into();
 
     let paused_button_event_listener_internal = paused.clone();
     let pause_button_listener = EventListener::new_with_options(&pause_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
         toggle_pause(&paused_button_event_listener_internal);
     });
 
-    let rho_slider_event_listener_internal = rho.clone();
-    let rho_slider_listener = EventListener::new_with_options(&rho_slider, "input", EventListenerOptions::enable_prevent_default(), move |_event| {
-        let rho_slider = document().get_element_by_id("rho-slider").unwrap();
-        let rho_slider: web_sys::HtmlInputElement = rho_slider.dyn_into::<web_sys::HtmlInputElement>().unwrap();
-        let rho_slider_label = document().get_element_by_id("rho-slider-label").unwrap();
-        let rho_slider_label: web_sys::HtmlLabelElement = rho_slider_label.dyn_into::<web_sys::HtmlLabelElement>().unwrap();
-        log!("new rho value{:?}", rho_slider.value());
-        *rho_slider_event_listener_internal.borrow_mut() = rho_slider.value().parse::<f32>().unwrap().into();
-        rho_slider_label.set_inner_text(&format!("rho = {}", rho_slider.value()));
-    });
-
-    let beta_slider_event_listener_internal = beta.clone();
-    let beta_slider_listener = EventListener::new_with_options(&beta_slider, "input", EventListenerOptions::enable_prevent_default(), move |_event| {
-        let beta_slider = document().get_element_by_id("beta-slider").unwrap();
-        let beta_slider: web_sys::HtmlInputElement = beta_slider.dyn_into::<web_sys::HtmlInputElement>().unwrap();
-        let beta_slider_label = document().get_element_by_id("beta-slider-label").unwrap();
-        let beta_slider_label: web_sys::HtmlLabelElement = beta_slider_label.dyn_into::<web_sys::HtmlLabelElement>().unwrap();
-        log!("new beta value{:?}", beta_slider.value());
-        *beta_slider_event_listener_internal.borrow_mut() = beta_slider.value().parse::<f32>().unwrap().into();
-        beta_slider_label.set_inner_text(&format!("beta = {}", beta_slider.value()));
-    });
-
-    let sigma_slider_event_listener_internal = sigma.clone();
-    let sigma_slider_listener = EventListener::new_with_options(&sigma_slider, "input", EventListenerOptions::enable_prevent_default(), move |_event| {
-        let sigma_slider = document().get_element_by_id("sigma-slider").unwrap();
-        let sigma_slider: web_sys::HtmlInputElement = sigma_slider.dyn_into::<web_sys::HtmlInputElement>().unwrap();
-        let sigma_slider_label = document().get_element_by_id("sigma-slider-label").unwrap();
-        let sigma_slider_label: web_sys::HtmlLabelElement = sigma_slider_label.dyn_into::<web_sys::HtmlLabelElement>().unwrap();
-        log!("new sigma value{:?}", sigma_slider.value());
-        *sigma_slider_event_listener_internal.borrow_mut() = sigma_slider.value().parse::<f32>().unwrap().into();
-        sigma_slider_label.set_inner_text(&format!("sigma = {}", sigma_slider.value()));
-    });
-
-    let camera_pos: Arc<RefCell<Vector3<f32>>> = RefCell::new(ORIGINAL_CAMERA_POS).into();
-    let camera_front: Arc<RefCell<Vector3<f32>>> = RefCell::new(Vector3::new(0.0, 0.0, -1.0)).into();
-    let camera_up: Arc<RefCell<Vector3<f32>>> = RefCell::new(Vector3::new(0.0, 1.0, 0.0)).into();
-
-    let mut pitch = 0.0;
-    let mut yaw = -90.0;
-    let camera_front_mousemove_internal = camera_front.clone();
+    let system = &SYSTEMS[0];
+    let camera: Arc<RefCell<Camera>> = RefCell::new(Camera::new(system_home(system))).into();
+
+    let settings: Arc<RefCell<Settings>> = RefCell::new(Settings { random_range: system.spawn_range, seed: rand::random(), ..Default::default() }).into();
+    let simulation: Arc<RefCell<Simulation>> = RefCell::new(Simulation::new(system, settings.borrow().max_trajectories, InitialDistribution::cube(settings.borrow().random_range), settings.borrow().seed)).into();
+
+    // Restore a shared scene from the URL fragment
+    let fragment = window().location().hash().unwrap_or_default();
+    if !fragment.is_empty() {
+        let mut simulation = simulation.borrow_mut();
+        let mut settings = settings.borrow_mut();
+        let mut camera = camera.borrow_mut();
+        let restored = Scene::from_fragment(&fragment, current_scene(&simulation, &settings, &camera))
+            .and_then(|scene| apply_scene(&scene, &mut simulation, &mut settings, &mut camera));
+        if let Err(e) = restored {
+            log!(format!("Ignoring the scene in the URL: {}", e));
+        }
+    }
+    populate_integrator_select();
+    populate_trail_style_select();
+    show_settings(&settings.borrow());
+    show_colour_scheme(&simulation.borrow().colour_scheme);
+    show_distribution(&simulation.borrow().distribution);
+
+    // Create the initial points
+    for _ in 0..settings.borrow().num_trajectories {
+        simulation.borrow_mut().spawn_random();
+    }
+    let selected: Arc<RefCell<Option<u32>>> = Default::default();
+
+    let system = simulation.borrow().system;
+    populate_system_select(system);
+    let parameter_listeners: Rc<RefCell<Vec<EventListener>>> = RefCell::new(build_parameter_controls(system, &simulation.borrow().parameters, &simulation)).into();
+
+    let sweep: Arc<RefCell<Option<ParameterSweep>>> = Default::default();
+    populate_sweep_parameters(system);
+
+    let simulation_sweep_parameter_internal = simulation.clone();
+    let sweep_parameter_listener = EventListener::new(&select_element("sweep-parameter"), "change", move |_event| {
+        show_sweep_range(simulation_sweep_parameter_internal.borrow().system);
+    });
+
+    let simulation_sweep_start_internal = simulation.clone();
+    let sweep_start_internal = sweep.clone();
+    let sweep_start_listener = EventListener::new_with_options(&html_element("sweep-start-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        match read_sweep(simulation_sweep_start_internal.borrow().system) {
+            Ok(new_sweep) => *sweep_start_internal.borrow_mut() = Some(new_sweep),
+            Err(e) => set_label("sweep-label", &e),
+        }
+    });
+
+    let sweep_stop_internal = sweep.clone();
+    let sweep_stop_listener = EventListener::new_with_options(&html_element("sweep-stop-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        *sweep_stop_internal.borrow_mut() = None;
+        set_label("sweep-label", "");
+    });
+
+    let mut all_presets = builtin_presets();
+    all_presets.extend(load_user_presets());
+    let parameter_presets: Arc<RefCell<Vec<Preset>>> = RefCell::new(all_presets).into();
+    refresh_preset_select(&parameter_presets.borrow(), system);
+
+    let simulation_preset_select_internal = simulation.clone();
+    let presets_select_internal = parameter_presets.clone();
+    let preset_select_listener = EventListener::new(&select_element("preset-select"), "change", move |_event| {
+        let presets = presets_select_internal.borrow();
+        let preset = match select_element("preset-select").value().parse::<usize>().ok().and_then(|index| presets.get(index)) {
+            Some(preset) => preset,
+            None => return set_label("preset-description", ""),
+        };
+        let mut simulation = simulation_preset_select_internal.borrow_mut();
+        if preset.system != simulation.system.id {
+            return;
+        }
+        simulation.parameters = preset.parameters.clone();
+        for (index, value) in preset.parameters.iter().enumerate() {
+            show_parameter(index, *value);
+        }
+        set_label("preset-description", &preset.description);
+    });
+
+    let simulation_preset_save_internal = simulation.clone();
+    let presets_save_internal = parameter_presets.clone();
+    let preset_save_listener = EventListener::new_with_options(&html_element("preset-save-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let simulation = simulation_preset_save_internal.borrow();
+        let mut presets = presets_save_internal.borrow_mut();
+        let name_input = input_element("preset-name");
+        let name = match name_input.value().trim() {
+            "" => format!("my preset {}", presets.iter().filter(|preset| !preset.builtin).count() + 1),
+            name => name.to_string(),
+        };
+        let description = match input_element("preset-note").value().trim() {
+            "" => simulation.system.describe(&simulation.parameters),
+            note => note.to_string(),
+        };
+        presets.push(Preset { name, system: simulation.system.id.to_string(), parameters: simulation.parameters.clone(), description, builtin: false });
+        save_user_presets(&presets);
+        refresh_preset_select(&presets, simulation.system);
+        select_element("preset-select").set_value(&(presets.len() - 1).to_string());
+        name_input.set_value("");
+        input_element("preset-note").set_value("");
+    });
+
+    let simulation_preset_delete_internal = simulation.clone();
+    let presets_delete_internal = parameter_presets.clone();
+    let preset_delete_listener = EventListener::new_with_options(&html_element("preset-delete-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let mut presets = presets_delete_internal.borrow_mut();
+        if let Some(index) = select_element("preset-select").value().parse::<usize>().ok().filter(|index| presets.get(*index).is_some_and(|preset| !preset.builtin)) {
+            presets.remove(index);
+            save_user_presets(&presets);
+            refresh_preset_select(&presets, simulation_preset_delete_internal.borrow().system);
+        }
+    });
+
+    let presets_export_internal = parameter_presets.clone();
+    let preset_export_listener = EventListener::new_with_options(&html_element("preset-export-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        download_text("presets.json", "application/json", &presets_to_json(&presets_export_internal.borrow()));
+    });
+
+    let simulation_preset_import_internal = simulation.clone();
+    let presets_import_internal = parameter_presets.clone();
+    let preset_import_listener = EventListener::new(&input_element("preset-import-input"), "change", move |_event| {
+        let simulation = simulation_preset_import_internal.clone();
+        let presets = presets_import_internal.clone();
+        if let Some(file) = selected_file("preset-import-input") {
+            spawn_local(async move {
+                let imported = read_file_text(&file)
+                    .await
+                    .map_err(|e| format!("Could not read file: {:?}", e))
+                    .and_then(|text| presets_from_json(&text));
+                match imported {
+                    Ok(imported) => {
+                        let mut presets = presets.borrow_mut();
+                        let count = imported.len();
+                        presets.extend(imported);
+                        save_user_presets(&presets);
+                        refresh_preset_select(&presets, simulation.borrow().system);
+                        set_label("preset-description", &format!("Imported {} presets", count));
+                    },
+                    Err(e) => set_label("preset-description", &e),
+                }
+            });
+        }
+    });
+
+    let camera_mousemove_internal = camera.clone();
+    let settings_mousemove_internal = settings.clone();
     let mouse_move_listener = EventListener::new_with_options(&body(), "mousemove",  EventListenerOptions::enable_prevent_default(), move |event| {
-        if let Some(_) = doc.pointer_lock_element() {
+        if document().pointer_lock_element().is_some() {
             let event = event.dyn_ref::<web_sys::MouseEvent>().unwrap_throw();
+            let sensitivity = settings_mousemove_internal.borrow().sensitivity;
+            camera_mousemove_internal.borrow_mut().take_control();
+            camera_mousemove_internal.borrow_mut().rotate(-event.movement_x() as f32 * sensitivity, event.movement_y() as f32 * sensitivity);
+        }
+    });
 
-            yaw -= event.movement_x() as f32 * SENSITIVITY;
-            pitch += event.movement_y() as f32 * SENSITIVITY;
+    let camera_click_internal = camera.clone();
+    let simulation_click_internal = simulation.clone();
+    let selected_click_internal = selected.clone();
+    let canvas_click_internal = canvas.clone();
+    let canvas_click_listener = EventListener::new_with_options(&canvas, "mousedown", EventListenerOptions::enable_prevent_default(), move |event| {
+        match select_element("click-mode").value().as_str() {
+            "select" => {
+                let event = event.dyn_ref::<web_sys::MouseEvent>().unwrap_throw();
+                let simulation = simulation_click_internal.borrow();
+                let picked = nearest_trajectory(&simulation.trajectories, &camera_click_internal.borrow().view_projection(),
+                                                Vector2::new(event.offset_x() as f32, event.offset_y() as f32),
+                                                canvas_click_internal.client_width() as f32, canvas_click_internal.client_height() as f32,
+                                                PICK_RADIUS);
+                select_trajectory(&mut selected_click_internal.borrow_mut(), &simulation, picked);
+            },
+            "spawn" => {
+                let event = event.dyn_ref::<web_sys::MouseEvent>().unwrap_throw();
+                let offset = input_element("spawn-offset").value().parse::<f32>().unwrap_or(0.0);
+                let target = match SpawnTarget::parse(&select_element("spawn-target").value(), offset) {
+                    Some(target) => target,
+                    None => return,
+                };
+                let ndc = to_ndc(event.offset_x() as f32, event.offset_y() as f32,
+                                 canvas_click_internal.client_width() as f32, canvas_click_internal.client_height() as f32);
+                let camera = camera_click_internal.borrow();
+                if let Some(point) = camera.ray_direction(ndc).and_then(|direction| intersect(camera.position, direction, target)) {
+                    simulation_click_internal.borrow_mut().spawn_at(point);
+                }
+            },
+            _ => {
+                body().request_pointer_lock();
+            },
+        }
+    });
 
-            if pitch > 89.0 {
-                pitch = 89.0;
-            } else if pitch < -89.0 {
-                pitch = -89.0;
-            }
+    let camera_wheel_internal = camera.clone();
+    let wheel_listener = EventListener::new_with_options(&canvas, "wheel", EventListenerOptions::enable_prevent_default(), move |event| {
+        let event = event.dyn_ref::<web_sys::WheelEvent>().unwrap_throw();
+        let mut camera = camera_wheel_internal.borrow_mut();
+        let fov = camera.fov * (1.0 + event.delta_y() as f32 * ZOOM_SENSITIVITY);
+        camera.set_fov(fov);
+        input_element("fov-slider").set_value(&format!("{:.1}", camera.fov));
+        set_label("fov-slider-label", &format!("fov = {:.1}", camera.fov));
+    });
+
+    let camera_fov_internal = camera.clone();
+    let fov_slider = input_element("fov-slider");
+    fov_slider.set_min(&MIN_FOV.to_string());
+    fov_slider.set_max(&MAX_FOV.to_string());
+    fov_slider.set_value(&format!("{:.1}", camera.borrow().fov));
+    set_label("fov-slider-label", &format!("fov = {:.1}", camera.borrow().fov));
+    let fov_slider_listener = EventListener::new_with_options(&fov_slider, "input", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let fov_slider = input_element("fov-slider");
+        let fov = match fov_slider.value().parse::<f32>() {
+            Ok(fov) => fov,
+            Err(_) => return,
+        };
+        camera_fov_internal.borrow_mut().set_fov(fov);
+        set_label("fov-slider-label", &format!("fov = {}", fov_slider.value()));
+    });
 
-            *camera_front_mousemove_internal.borrow_mut() = Vector3::new(f32::cos(yaw.to_radians()) * f32::cos(pitch.to_radians()), 
-                                                                         f32::sin(pitch.to_radians()),
-                                                                         f32::sin(yaw.to_radians()) * f32::cos(pitch.to_radians()))
-                                                                         .into();
+    let camera_near_internal = camera.clone();
+    let near_slider_listener = EventListener::new_with_options(&input_element("near-slider"), "input", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let near_slider = input_element("near-slider");
+        let near = match near_slider.value().parse::<f32>() {
+            Ok(near) => near,
+            Err(_) => return,
+        };
+        let mut camera = camera_near_internal.borrow_mut();
+        camera.near = near.min(camera.far * 0.5);
+        set_label("near-slider-label", &format!("near = {}", camera.near));
+    });
+
+    let camera_far_internal = camera.clone();
+    let far_slider_listener = EventListener::new_with_options(&input_element("far-slider"), "input", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let far_slider = input_element("far-slider");
+        let far = match far_slider.value().parse::<f32>() {
+            Ok(far) => far,
+            Err(_) => return,
+        };
+        let mut camera = camera_far_internal.borrow_mut();
+        camera.far = far.max(camera.near * 2.0);
+        set_label("far-slider-label", &format!("far = {}", camera.far));
+    });
+
+    let camera_follow_distance_internal = camera.clone();
+    let follow_distance_slider_listener = EventListener::new_with_options(&input_element("follow-distance-slider"), "input", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let follow_distance_slider = input_element("follow-distance-slider");
+        let distance = match follow_distance_slider.value().parse::<f32>() {
+            Ok(distance) => distance,
+            Err(_) => return,
+        };
+        camera_follow_distance_internal.borrow_mut().follow_distance = distance;
+        set_label("follow-distance-slider-label", &format!("distance = {}", follow_distance_slider.value()));
+    });
+
+    let camera_follow_smoothing_internal = camera.clone();
+    let follow_smoothing_slider_listener = EventListener::new_with_options(&input_element("follow-smoothing-slider"), "input", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let follow_smoothing_slider = input_element("follow-smoothing-slider");
+        let smoothing = match follow_smoothing_slider.value().parse::<f32>() {
+            Ok(smoothing) => smoothing,
+            Err(_) => return,
+        };
+        camera_follow_smoothing_internal.borrow_mut().follow_smoothing = smoothing;
+        set_label("follow-smoothing-slider-label", &format!("smoothing = {}", follow_smoothing_slider.value()));
+    });
+
+    let camera_follow_internal = camera.clone();
+    let simulation_follow_internal = simulation.clone();
+    let follow_button = doc.get_element_by_id("follow-button").unwrap();
+    let follow_button_listener = EventListener::new_with_options(&follow_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let simulation = simulation_follow_internal.borrow();
+        // Fall back to the newest trajectory if the id isn't valid
+        let id = input_element("follow-id")
+            .value()
+            .parse::<u32>()
+            .ok()
+            .filter(|id| simulation.get(*id).is_some())
+            .or_else(|| simulation.trajectories.last().map(|trajectory| trajectory.id));
+        follow_trajectory(&mut camera_follow_internal.borrow_mut(), id);
+    });
+
+    let camera_unfollow_internal = camera.clone();
+    let unfollow_button = doc.get_element_by_id("unfollow-button").unwrap();
+    let unfollow_button_listener = EventListener::new_with_options(&unfollow_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        follow_trajectory(&mut camera_unfollow_internal.borrow_mut(), None);
+    });
+
+    let camera_turntable_internal = camera.clone();
+    let simulation_turntable_internal = simulation.clone();
+    let turntable_button = doc.get_element_by_id("turntable-button").unwrap();
+    let turntable_button_listener = EventListener::new_with_options(&turntable_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        toggle_turntable(&mut camera_turntable_internal.borrow_mut(), &simulation_turntable_internal.borrow());
+    });
+
+    let camera_turntable_speed_internal = camera.clone();
+    let turntable_speed_slider_listener = EventListener::new_with_options(&input_element("turntable-speed-slider"), "input", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let turntable_speed_slider = input_element("turntable-speed-slider");
+        let speed = match turntable_speed_slider.value().parse::<f32>() {
+            Ok(speed) => speed,
+            Err(_) => return,
+        };
+        camera_turntable_speed_internal.borrow_mut().turntable_speed = speed;
+        set_label("turntable-speed-slider-label", &format!("speed = {} deg/s", turntable_speed_slider.value()));
+    });
+
+    // Camera path editor
+    let path: Arc<RefCell<CameraPath>> = Default::default();
+    show_path(&path.borrow());
+
+    let camera_path_record_internal = camera.clone();
+    let path_record_internal = path.clone();
+    let path_record_button = doc.get_element_by_id("path-record-button").unwrap();
+    let path_record_listener = EventListener::new_with_options(&path_record_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let time_input = input_element("path-time");
+        let mut path = path_record_internal.borrow_mut();
+        let time = time_input.value().parse::<f32>().ok().filter(|time| time.is_finite() && *time >= 0.0).unwrap_or(path.duration());
+        path.record(time, camera_path_record_internal.borrow().pose());
+        time_input.set_value(&format!("{}", time + PATH_KEYFRAME_SPACING));
+        show_path(&path);
+    });
+
+    let path_clear_internal = path.clone();
+    let path_clear_button = doc.get_element_by_id("path-clear-button").unwrap();
+    let path_clear_listener = EventListener::new_with_options(&path_clear_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let mut path = path_clear_internal.borrow_mut();
+        path.keyframes.clear();
+        input_element("path-time").set_value("0");
+        show_path(&path);
+    });
+
+    let camera_path_play_internal = camera.clone();
+    let path_play_internal = path.clone();
+    let path_play_button = doc.get_element_by_id("path-play-button").unwrap();
+    let path_play_listener = EventListener::new_with_options(&path_play_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let path = path_play_internal.borrow();
+        if !path.keyframes.is_empty() {
+            camera_path_play_internal.borrow_mut().play(PathPlayback::new(path.clone(), input_element("path-loop").checked()));
         }
     });
 
-    let canvas_click_listener = EventListener::new_with_options(&canvas, "mousedown", EventListenerOptions::enable_prevent_default(), move |_event| {
-        body().request_pointer_lock();
+    let path_save_internal = path.clone();
+    let path_save_button = doc.get_element_by_id("path-save-button").unwrap();
+    let path_save_listener = EventListener::new_with_options(&path_save_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        download_text("camera_path.json", "application/json", &path_save_internal.borrow().to_json());
+    });
+
+    let path_load_internal = path.clone();
+    let path_load_listener = EventListener::new(&input_element("path-load-input"), "change", move |_event| {
+        let path = path_load_internal.clone();
+        if let Some(file) = selected_file("path-load-input") {
+            spawn_local(async move {
+                let loaded = read_file_text(&file)
+                    .await
+                    .map_err(|e| format!("Could not read file: {:?}", e))
+                    .and_then(|text| CameraPath::from_json(&text));
+                match loaded {
+                    Ok(loaded) => {
+                        *path.borrow_mut() = loaded;
+                        show_path(&path.borrow());
+                    },
+                    Err(e) => set_label("path-label", &e),
+                }
+            });
+        }
     });
 
-    let camera_pos_keydown_internal = camera_pos.clone();
-    let camera_front_keydown_internal = camera_front.clone();
-    let camera_up_keydown_internal = camera_up.clone();
+    let camera_keydown_internal = camera.clone();
+    let simulation_keydown_internal = simulation.clone();
+    let settings_keydown_internal = settings.clone();
 
     let paused_keyboard_listener = paused.clone();
     let keydown_listener = EventListener::new_with_options(&window(), "keydown", EventListenerOptions::enable_prevent_default(), move |event| {
         let event = event.dyn_ref::<web_sys::KeyboardEvent>().unwrap_throw();
+        // Don't steer the camera while typing into a text box
+        if event.target().and_then(|target| target.dyn_into::<web_sys::HtmlInputElement>().ok()).is_some_and(|input| matches!(input.type_().as_str(), "text" | "number")) {
+            return;
+        }
         let key = event.key();
-        let mut camera_pos = camera_pos_keydown_internal.borrow_mut();
-        let mut camera_front = camera_front_keydown_internal.borrow_mut();
-        let camera_up = *camera_up_keydown_internal.borrow();
+        let mut camera = camera_keydown_internal.borrow_mut();
+        if !matches!(key.as_str(), " " | "f" | "t") {
+            camera.take_control();
+        }
+        let camera_front = camera.front;
+        let camera_up = camera.up;
+        let camera_right = camera.right();
+        let camera_speed = settings_keydown_internal.borrow().camera_speed;
         match key.as_str() {
             "w" => {
-                *camera_pos = (*camera_pos + CAMERA_SPEED * *camera_front).into(); 
+                camera.position += camera_speed * camera_front;
             },
             "s" => {
-                *camera_pos = (*camera_pos - CAMERA_SPEED * *camera_front).into(); 
+                camera.position -= camera_speed * camera_front;
             },
             "a" => {
-                *camera_pos = (*camera_pos + CAMERA_SPEED * camera_front.cross(&camera_up)).into(); 
+                camera.position += camera_speed * camera_right;
             },
             "d" => {
-                *camera_pos = (*camera_pos - CAMERA_SPEED * camera_front.cross(&camera_up)).into(); 
+                camera.position -= camera_speed * camera_right;
             },
             "Shift" => {
-                *camera_pos = (*camera_pos - CAMERA_SPEED * &camera_up).into(); 
+                camera.position -= camera_speed * camera_up;
             },
             "Control" => {
-                *camera_pos = (*camera_pos + CAMERA_SPEED * &camera_up).into(); 
+                camera.position += camera_speed * camera_up;
             },
             "ArrowUp" => {
-                *camera_front = (*camera_front - Vector3::new(0.0, CAMERA_ROTATION.to_radians(), 0.0)).normalize();
+                camera.rotate(0.0, -CAMERA_ROTATION);
             },
             "ArrowDown" => {
-                *camera_front = (*camera_front + Vector3::new(0.0, CAMERA_ROTATION.to_radians(), 0.0)).normalize();
+                camera.rotate(0.0, CAMERA_ROTATION);
             },
             "ArrowRight" => {
-                *camera_front = (*camera_front - Vector3::new(CAMERA_ROTATION.to_radians(), 0.0, 0.0)).normalize();
+                camera.rotate(-CAMERA_ROTATION, 0.0);
             },
             "ArrowLeft" => {
-                *camera_front = (*camera_front + Vector3::new(CAMERA_ROTATION.to_radians(), 0.0, 0.0)).normalize();
+                camera.rotate(CAMERA_ROTATION, 0.0);
             },
             " " => {
                 toggle_pause(&paused_keyboard_listener);
             },
+            "t" => {
+                toggle_turntable(&mut camera, &simulation_keydown_internal.borrow());
+            },
+            "f" => {
+                // Cycle through the trajectories
+                let simulation = simulation_keydown_internal.borrow();
+                let next = simulation.trajectories.iter()
+                    .find(|trajectory| camera.follow.is_some_and(|id| trajectory.id > id))
+                    .or_else(|| simulation.trajectories.first())
+                    .map(|trajectory| trajectory.id);
+                follow_trajectory(&mut camera, next);
+            },
             _ => {log!("Unused key down", key)}
         }
     });
@@ -237,112 +745,689 @@ fn start() -> Result<(), JsValue> {
         .unwrap()
         .dyn_into::<WebGl2RenderingContext>()?;
 
-    let vert_shader = compile_shader(&context, WebGl2RenderingContext::VERTEX_SHADER, VERTEX_SHADER_TEXT)?;
-    let frag_shader = compile_shader(&context, WebGl2RenderingContext::FRAGMENT_SHADER, FRAGMENT_SHADER_TEXT)?;
-    let program = link_program(&context, &vert_shader, &frag_shader)?;
-    context.use_program(Some(&program));
-
-    let colour_uniform_location = context.get_uniform_location(&program, "uColour");
-    let modelviewprojection_uniform_location = context.get_uniform_location(&program, "uMVP");
-    let position_attribute_location = context.get_attrib_location(&program, "position");
-    let buffer = context.create_buffer().ok_or("Failed to create buffer")?;
-    context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&buffer));
-
-    let vertex_array_object = context
-        .create_vertex_array()
-        .ok_or("Could not create vertex array object")?;
-    context.bind_vertex_array(Some(&vertex_array_object));
-    context.vertex_attrib_pointer_with_i32(
-        position_attribute_location as u32,
-        3,
-        WebGl2RenderingContext::FLOAT,
-        true,
-        0,
-        0,
-    );
-    context.enable_vertex_attrib_array(position_attribute_location as u32);
-    context.bind_vertex_array(Some(&vertex_array_object));
+    let line_strips = LineStripRenderer::new(&context, VERTEX_SHADER_TEXT, FRAGMENT_SHADER_TEXT)?;
+    let thick_lines = ThickLineRenderer::new(&context)?;
+    let shaded_trails = ShadedTrailRenderer::new(&context)?;
+    let points = Rc::new(PointRenderer::new(&context)?);
+    context.enable(WebGl2RenderingContext::DEPTH_TEST);
+    context.depth_func(WebGl2RenderingContext::LEQUAL);
+
+    camera.borrow_mut().aspect = resize_canvas(&canvas, &context, window().device_pixel_ratio());
+
+    // Scale of a screenshot to take after the next frame
+    let screenshot: Rc<RefCell<Option<i32>>> = Default::default();
+    let screenshot_button_internal = screenshot.clone();
+    let screenshot_listener = EventListener::new_with_options(&html_element("screenshot-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let scale = select_element("screenshot-scale").value().parse().unwrap_or(1);
+        *screenshot_button_internal.borrow_mut() = Some(scale);
+    });
+
+    let recording: Rc<RefCell<Option<Recording>>> = Default::default();
+    let recording_start_internal = recording.clone();
+    let canvas_recording_internal = canvas.clone();
+    let record_listener = EventListener::new_with_options(&html_element("record-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        if recording_start_internal.borrow().is_some() {
+            return;
+        }
+        let seconds = input_element("record-seconds").value().parse::<f32>().ok().filter(|seconds| seconds.is_finite() && *seconds > 0.0);
+        let fps = input_element("record-fps").value().parse::<u32>().ok().filter(|fps| (1..=60).contains(fps));
+        let (seconds, fps) = match (seconds, fps) {
+            (Some(seconds), Some(fps)) => (seconds, fps),
+            _ => return set_label("record-label", "length must be positive and the frame rate between 1 and 60"),
+        };
+        let started = match select_element("record-format").value().as_str() {
+            "gif" => Ok(Recording::gif(&canvas_recording_internal, fps, seconds)),
+            _ => Recording::webm(&canvas_recording_internal, fps, seconds),
+        };
+        match started {
+            Ok(started) => *recording_start_internal.borrow_mut() = Some(started),
+            Err(e) => set_label("record-label", &e),
+        }
+    });
+
+    let recording_stop_internal = recording.clone();
+    let simulation_recording_stop_internal = simulation.clone();
+    let record_stop_listener = EventListener::new_with_options(&html_element("record-stop-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        if let Some(stopped) = recording_stop_internal.borrow_mut().take() {
+            stopped.finish(simulation_recording_stop_internal.borrow().system.id);
+            set_label("record-label", "recording stopped");
+        }
+    });
+
+    let ensemble: Rc<RefCell<Option<ActiveEnsemble>>> = Default::default();
+    let point_size = Rc::new(Cell::new(2.0f32));
+    let ensemble_start_internal = ensemble.clone();
+    let simulation_ensemble_internal = simulation.clone();
+    let context_ensemble_internal = context.clone();
+    let points_ensemble_internal = points.clone();
+    let ensemble_listener = EventListener::new_with_options(&html_element("ensemble-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let count = match input_element("ensemble-count").value().trim().parse::<usize>().ok().filter(|count| (1..=MAX_ENSEMBLE_SIZE).contains(count)) {
+            Some(count) => count,
+            None => return set_label("ensemble-label", &format!("particle count must be between 1 and {}", MAX_ENSEMBLE_SIZE)),
+        };
+        let simulation = simulation_ensemble_internal.borrow();
+        let particles = Ensemble::new(count, &simulation.distribution, &simulation.colour_scheme, simulation.seed);
+        let started = match select_element("ensemble-backend").value().as_str() {
+            "gpu" => GpuEnsemble::new(&context_ensemble_internal, &points_ensemble_internal, simulation.system, &particles).map(ActiveEnsemble::Gpu),
+            _ => Ok(ActiveEnsemble::Cpu(particles)),
+        };
+        match started {
+            Ok(started) => {
+                set_label("ensemble-label", &format!("{} particles", started.count()));
+                *ensemble_start_internal.borrow_mut() = Some(started);
+            },
+            Err(e) => set_label("ensemble-label", &e),
+        }
+    });
+
+    // Runs a small ensemble on both backends and reports how far apart they end up
+    let simulation_ensemble_check_internal = simulation.clone();
+    let settings_ensemble_check_internal = settings.clone();
+    let context_ensemble_check_internal = context.clone();
+    let points_ensemble_check_internal = points.clone();
+    let ensemble_check_listener = EventListener::new_with_options(&html_element("ensemble-check-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        const CHECK_PARTICLES: usize = 1000;
+        const CHECK_STEPS: usize = 100;
+        let simulation = simulation_ensemble_check_internal.borrow();
+        let settings = *settings_ensemble_check_internal.borrow();
+        let particles = Ensemble::new(CHECK_PARTICLES, &simulation.distribution, &simulation.colour_scheme, simulation.seed);
+        let compared = compare_with_cpu(&context_ensemble_check_internal, &points_ensemble_check_internal, simulation.system, &simulation.parameters,
+            settings.integrator, settings.delta_t, settings.bounds, &particles, CHECK_STEPS);
+        set_label("ensemble-label", &match compared {
+            Ok(difference) if difference <= GPU_TOLERANCE => format!("GPU matches CPU: largest relative difference {:.1e} after {} steps", difference, CHECK_STEPS),
+            Ok(difference) => format!("GPU differs from CPU: largest relative difference {:.1e} after {} steps", difference, CHECK_STEPS),
+            Err(e) => format!("GPU check failed: {}", e),
+        });
+    });
+
+    let ensemble_stop_internal = ensemble.clone();
+    let ensemble_stop_listener = EventListener::new_with_options(&html_element("ensemble-stop-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        *ensemble_stop_internal.borrow_mut() = None;
+        set_label("ensemble-label", "");
+    });
+
+    let point_size_internal = point_size.clone();
+    let point_size_listener = EventListener::new(&input_element("ensemble-point-size"), "change", move |_event| {
+        match input_element("ensemble-point-size").value().trim().parse::<f32>().ok().filter(|size| size.is_finite() && *size > 0.0 && *size <= 64.0) {
+            Some(size) => point_size_internal.set(size),
+            None => set_label("ensemble-label", "point size must be between 0 and 64 pixels"),
+        }
+    });
+
+    let density: Rc<RefCell<Option<DensityHistogram>>> = Default::default();
+    let density_enabled = Rc::new(Cell::new(false));
+    // Changing the resolution starts over, the plane only changes what is shown
+    let density_controls_internal = density.clone();
+    let density_enabled_internal = density_enabled.clone();
+    let density_controls_listener = EventListener::new(&html_element("density-controls"), "change", move |event| {
+        let target = event.target().and_then(|target| target.dyn_into::<web_sys::Element>().ok()).map(|element| element.id());
+        if target.as_deref() == Some("density-resolution") {
+            *density_controls_internal.borrow_mut() = None;
+        }
+        density_enabled_internal.set(input_element("density-enabled").checked());
+    });
+
+    let density_reset_internal = density.clone();
+    let density_reset_listener = EventListener::new_with_options(&html_element("density-reset-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        *density_reset_internal.borrow_mut() = None;
+        set_label("density-label", "");
+    });
+
+    let simulation_export_internal = simulation.clone();
+    let export_listener = EventListener::new_with_options(&html_element("export-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        if let Some(format) = ExportFormat::parse(&select_element("export-format").value()) {
+            let simulation = simulation_export_internal.borrow();
+            let filename = format!("{}-trajectories.{}", simulation.system.id, format.extension());
+            download_bytes(&filename, format.mime(), &format.export(&simulation));
+        }
+    });
+
+    let simulation_import_internal = simulation.clone();
+    let import_listener = EventListener::new(&input_element("import-input"), "change", move |_event| {
+        if let Some(file) = selected_file("import-input") {
+            import_file(file, simulation_import_internal.clone());
+        }
+    });
+
+    // The canvas only accepts a drop if dragover is cancelled
+    let canvas_dragover_listener = EventListener::new_with_options(&canvas, "dragover", EventListenerOptions::enable_prevent_default(), move |event| {
+        event.prevent_default();
+    });
+
+    let simulation_drop_internal = simulation.clone();
+    let canvas_drop_listener = EventListener::new_with_options(&canvas, "drop", EventListenerOptions::enable_prevent_default(), move |event| {
+        event.prevent_default();
+        let files = event.dyn_ref::<web_sys::DragEvent>().and_then(|event| event.data_transfer()).and_then(|transfer| transfer.files());
+        if let Some(files) = files {
+            for i in 0..files.length() {
+                if let Some(file) = files.get(i) {
+                    import_file(file, simulation_drop_internal.clone());
+                }
+            }
+        }
+    });
+
+    let simulation_mesh_internal = simulation.clone();
+    let selected_mesh_internal = selected.clone();
+    let mesh_export_listener = EventListener::new_with_options(&html_element("mesh-export-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let simulation = simulation_mesh_internal.borrow();
+        let trajectories: Vec<&Trajectory> = match select_element("mesh-scope").value().as_str() {
+            "selected" => selected_mesh_internal.borrow().and_then(|id| simulation.get(id)).into_iter().collect(),
+            _ => simulation.trajectories.iter().collect(),
+        };
+        let radius = input_element("mesh-radius").value().parse::<f32>().ok().filter(|radius| radius.is_finite() && *radius > 0.0);
+        let segments = input_element("mesh-segments").value().parse::<u32>().ok().filter(|segments| (3..=64).contains(segments));
+        let (radius, segments) = match (radius, segments) {
+            (Some(radius), Some(segments)) => (radius, segments),
+            _ => return set_label("mesh-label", "radius must be positive and segments between 3 and 64"),
+        };
+
+        let format = select_element("mesh-format").value();
+        let mesh = if format == "points" { Mesh::point_cloud(&trajectories) } else { Mesh::tubes(&trajectories, radius, segments) };
+        if mesh.positions.is_empty() {
+            return set_label("mesh-label", "nothing to export, select a trajectory or choose all trajectories");
+        }
+        let name = format!("{}-trails", simulation.system.id);
+        match format.as_str() {
+            "obj" => download_text(&format!("{}.obj", name), "model/obj", &mesh.to_obj()),
+            "stl" => download_bytes(&format!("{}.stl", name), "model/stl", &mesh.to_stl()),
+            "glb" => download_bytes(&format!("{}.glb", name), "model/gltf-binary", &mesh.to_glb()),
+            "points" => download_bytes(&format!("{}-points.ply", name), "application/octet-stream", &mesh.to_ply()),
+            _ => download_bytes(&format!("{}.ply", name), "application/octet-stream", &mesh.to_ply()),
+        }
+        set_label("mesh-label", &format!("{} vertices, {} triangles", mesh.positions.len(), mesh.triangles.len()));
+    });
+
+    let simulation_svg_internal = simulation.clone();
+    let selected_svg_internal = selected.clone();
+    let camera_svg_internal = camera.clone();
+    let settings_svg_internal = settings.clone();
+    let canvas_svg_internal = canvas.clone();
+    let svg_export_listener = EventListener::new_with_options(&html_element("svg-export-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let stroke_width = match input_element("svg-stroke-width").value().parse::<f32>().ok().filter(|width| width.is_finite() && *width > 0.0) {
+            Some(width) => width,
+            None => return,
+        };
+        let simulation = simulation_svg_internal.borrow();
+        let bounds = settings_svg_internal.borrow().bounds;
+        let mut svg = SvgWriter::new(camera_svg_internal.borrow().view_projection(), canvas_svg_internal.width() as f32, canvas_svg_internal.height() as f32);
+        for trajectory in simulation.trajectories.iter() {
+            svg.polyline(&trajectory.points, &trajectory.colour, stroke_width);
+        }
+        if let Some(trajectory) = selected_svg_internal.borrow().and_then(|id| simulation.get(id)) {
+            svg.polyline(&trajectory.points, &HIGHLIGHT_COLOUR, stroke_width * 2.0);
+        }
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        for axis in 0..3 {
+            let mut end = Vector3::zeros();
+            end[axis] = bounds;
+            svg.polyline(&[-end, end], &white, stroke_width);
+        }
+        download_text(&format!("{}.svg", simulation.system.id), "image/svg+xml", &svg.finish());
+    });
 
     let f = Rc::new(RefCell::new(None));
     let g = f.clone();
 
-    // Create the initial points
-    let data: Arc<RefCell<Vec<Vec<Vector3<f32>>>>> = Default::default();
-    let colours: Arc<RefCell<Vec<Vector3<f32>>>> = Default::default(); 
-    for _ in 0..NUM_TRAJECTORIES {
-        spawn_random_trajectory(&mut data.borrow_mut(), &mut colours.borrow_mut());
-    }
+    let bookmarks: Arc<RefCell<Vec<Bookmark>>> = RefCell::new(presets(system_home(system), system.spawn_range)).into();
+    refresh_bookmark_select(&bookmarks.borrow());
+
+    let camera_bookmark_select_internal = camera.clone();
+    let bookmarks_select_internal = bookmarks.clone();
+    let bookmark_select_listener = EventListener::new(&bookmark_select(), "change", move |_event| {
+        let index = match bookmark_select().value().parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => return,
+        };
+        if let Some(bookmark) = bookmarks_select_internal.borrow().get(index) {
+            camera_bookmark_select_internal.borrow_mut().fly_to(&bookmark.pose);
+        }
+    });
 
-    let data_render_loop_internal = data.clone();
-    let colours_render_loop_internal = colours.clone();
+    let camera_bookmark_save_internal = camera.clone();
+    let bookmarks_save_internal = bookmarks.clone();
+    let bookmark_save_button = doc.get_element_by_id("bookmark-save-button").unwrap();
+    let bookmark_save_listener = EventListener::new_with_options(&bookmark_save_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let name_input = input_element("bookmark-name");
+        let mut bookmarks = bookmarks_save_internal.borrow_mut();
+        let name = match name_input.value().trim() {
+            "" => format!("view {}", bookmarks.len() + 1),
+            name => name.to_string(),
+        };
+        bookmarks.push(Bookmark { name, pose: camera_bookmark_save_internal.borrow().pose(), preset: false });
+        refresh_bookmark_select(&bookmarks);
+        bookmark_select().set_value(&(bookmarks.len() - 1).to_string());
+        name_input.set_value("");
+    });
+
+    let bookmarks_delete_internal = bookmarks.clone();
+    let bookmark_delete_button = doc.get_element_by_id("bookmark-delete-button").unwrap();
+    let bookmark_delete_listener = EventListener::new_with_options(&bookmark_delete_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let index = match bookmark_select().value().parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => return,
+        };
+        let mut bookmarks = bookmarks_delete_internal.borrow_mut();
+        if bookmarks.get(index).is_some_and(|bookmark| !bookmark.preset) {
+            bookmarks.remove(index);
+            refresh_bookmark_select(&bookmarks);
+        }
+    });
+
+    let camera_restart_internal = camera.clone();
+    let simulation_restart_internal = simulation.clone();
+    let settings_restart_internal = settings.clone();
+    let restart_button = doc.get_element_by_id("restart-button").unwrap();
+    let restart_listener = EventListener::new_with_options(&restart_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let mut simulation = simulation_restart_internal.borrow_mut();
+        simulation.clear();
+        for _ in 0..settings_restart_internal.borrow().num_trajectories {
+            simulation.spawn_random();
+        }
+        camera_restart_internal.borrow_mut().fly_to(&home_pose(system_home(simulation.system)));
+        bookmark_select().set_value("0");
+    });
+
+    show_distribution_parameters();
+    let simulation_distribution_internal = simulation.clone();
+    let distribution_listener = EventListener::new(&html_element("distribution-controls"), "change", move |_event| {
+        show_distribution_parameters();
+        match read_distribution() {
+            Ok(distribution) => {
+                simulation_distribution_internal.borrow_mut().distribution = distribution;
+                set_label("distribution-error", "");
+            },
+            Err(e) => set_label("distribution-error", &e),
+        }
+    });
+
+    let simulation_burst_internal = simulation.clone();
+    let burst_button = doc.get_element_by_id("burst-button").unwrap();
+    let burst_listener = EventListener::new_with_options(&burst_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let count = input_element("burst-count").value().parse::<usize>().unwrap_or(0);
+        let mut simulation = simulation_burst_internal.borrow_mut();
+        for _ in 0..count {
+            simulation.spawn_random();
+        }
+    });
+
+    let simulation_colour_internal = simulation.clone();
+    let selected_colour_internal = selected.clone();
+    let inspector_colour_listener = EventListener::new(&input_element("inspector-colour"), "input", move |_event| {
+        let mut simulation = simulation_colour_internal.borrow_mut();
+        let trajectory = selected_colour_internal.borrow().and_then(|id| simulation.get_mut(id));
+        if let (Some(trajectory), Some(colour)) = (trajectory, from_hex(&input_element("inspector-colour").value())) {
+            trajectory.colour = colour;
+        }
+    });
+
+    let simulation_pin_internal = simulation.clone();
+    let selected_pin_internal = selected.clone();
+    let inspector_pin_listener = EventListener::new(&input_element("inspector-pin"), "change", move |_event| {
+        let mut simulation = simulation_pin_internal.borrow_mut();
+        if let Some(trajectory) = selected_pin_internal.borrow().and_then(|id| simulation.get_mut(id)) {
+            trajectory.pinned = input_element("inspector-pin").checked();
+        }
+    });
+
+    let camera_inspector_follow_internal = camera.clone();
+    let selected_follow_internal = selected.clone();
+    let inspector_follow_button = doc.get_element_by_id("inspector-follow-button").unwrap();
+    let inspector_follow_listener = EventListener::new_with_options(&inspector_follow_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        follow_trajectory(&mut camera_inspector_follow_internal.borrow_mut(), *selected_follow_internal.borrow());
+    });
+
+    let simulation_delete_internal = simulation.clone();
+    let selected_delete_internal = selected.clone();
+    let inspector_delete_button = doc.get_element_by_id("inspector-delete-button").unwrap();
+    let inspector_delete_listener = EventListener::new_with_options(&inspector_delete_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let mut simulation = simulation_delete_internal.borrow_mut();
+        let mut selected = selected_delete_internal.borrow_mut();
+        if let Some(id) = *selected {
+            simulation.remove(id);
+        }
+        select_trajectory(&mut selected, &simulation, None);
+    });
+
+    let simulation_deselect_internal = simulation.clone();
+    let selected_deselect_internal = selected.clone();
+    let inspector_close_button = doc.get_element_by_id("inspector-close-button").unwrap();
+    let inspector_close_listener = EventListener::new_with_options(&inspector_close_button, "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        select_trajectory(&mut selected_deselect_internal.borrow_mut(), &simulation_deselect_internal.borrow(), None);
+    });
+
+    let simulation_system_internal = simulation.clone();
+    let settings_system_internal = settings.clone();
+    let camera_system_internal = camera.clone();
+    let bookmarks_system_internal = bookmarks.clone();
+    let presets_system_internal = parameter_presets.clone();
+    let sweep_system_internal = sweep.clone();
+    let ensemble_system_internal = ensemble.clone();
+    let density_system_internal = density.clone();
+    let system_select_listener = EventListener::new(&select_element("system-select"), "change", move |_event| {
+        let system = match find_system(&select_element("system-select").value()) {
+            Some(system) => system,
+            None => return,
+        };
+        let mut simulation = simulation_system_internal.borrow_mut();
+        let mut settings = settings_system_internal.borrow_mut();
+        simulation.set_system(system);
+        settings.random_range = system.spawn_range;
+        show_settings(&settings);
+        simulation.distribution = InitialDistribution::cube(system.spawn_range);
+        show_distribution(&simulation.distribution);
+        for _ in 0..settings.num_trajectories {
+            simulation.spawn_random();
+        }
+        *parameter_listeners.borrow_mut() = build_parameter_controls(system, &simulation.parameters, &simulation_system_internal);
+        refresh_preset_select(&presets_system_internal.borrow(), system);
+        *sweep_system_internal.borrow_mut() = None;
+        populate_sweep_parameters(system);
+        set_label("sweep-label", "");
+        // The particles belong to the old flow
+        *ensemble_system_internal.borrow_mut() = None;
+        set_label("ensemble-label", "");
+        *density_system_internal.borrow_mut() = None;
+        set_label("density-label", "");
+        let mut bookmarks = bookmarks_system_internal.borrow_mut();
+        replace_presets(&mut bookmarks, system_home(system), system.spawn_range);
+        refresh_bookmark_select(&bookmarks);
+        camera_system_internal.borrow_mut().fly_to(&home_pose(system_home(system)));
+    });
+
+    let simulation_settings_internal = simulation.clone();
+    let settings_panel_internal = settings.clone();
+    let settings_listener = EventListener::new(&html_element("settings-panel"), "change", move |_event| {
+        let new_settings = match read_settings() {
+            Ok(new_settings) => new_settings,
+            Err(e) => {
+                set_label("settings-error", &e);
+                return;
+            },
+        };
+        set_label("settings-error", "");
+
+        let mut settings = settings_panel_internal.borrow_mut();
+        let mut simulation = simulation_settings_internal.borrow_mut();
+        if new_settings.random_range != settings.random_range {
+            simulation.distribution = InitialDistribution::cube(new_settings.random_range);
+            show_distribution(&simulation.distribution);
+        }
+        if new_settings.seed != settings.seed {
+            simulation.reseed(new_settings.seed);
+        }
+        simulation.set_max_trajectories(new_settings.max_trajectories);
+        *settings = new_settings;
+    });
+
+    let simulation_colour_scheme_internal = simulation.clone();
+    let colour_scheme_listener = EventListener::new(&html_element("colour-controls"), "change", move |_event| {
+        if let Some(scheme) = read_colour_scheme() {
+            simulation_colour_scheme_internal.borrow_mut().set_colour_scheme(scheme);
+        }
+    });
+
+    let simulation_copy_link_internal = simulation.clone();
+    let settings_copy_link_internal = settings.clone();
+    let camera_copy_link_internal = camera.clone();
+    let copy_link_listener = EventListener::new_with_options(&html_element("copy-link-button"), "click", EventListenerOptions::enable_prevent_default(), move |_event| {
+        let scene = current_scene(&simulation_copy_link_internal.borrow(), &settings_copy_link_internal.borrow(), &camera_copy_link_internal.borrow());
+        let location = window().location();
+        location.set_hash(&scene.to_fragment()).unwrap();
+        let link = location.href().unwrap();
+        spawn_local(async move {
+            match copy_to_clipboard(&link).await {
+                Ok(()) => set_label("copy-link-label", "link copied"),
+                // Clipboard access can be refused, the link is still in the address bar
+                Err(_) => set_label("copy-link-label", "copy the link from the address bar"),
+            }
+        });
+    });
+
+    let simulation_render_loop_internal = simulation.clone();
+    let selected_render_loop_internal = selected.clone();
+    let settings_render_loop_internal = settings.clone();
+    let sweep_render_loop_internal = sweep.clone();
+    let screenshot_render_loop_internal = screenshot.clone();
+    let recording_render_loop_internal = recording.clone();
+    let ensemble_render_loop_internal = ensemble.clone();
+    let canvas_render_loop_internal = canvas.clone();
+    let mut last_frame: Option<f64> = None;
+    let mut last_spawn: Option<f64> = None;
+    let mut simulation_worker: Option<SimulationWorker> = None;
+    let mut frame: u64 = 0;
+    let density_render_loop_internal = density.clone();
+    let mut last_divergence: Option<f64> = None;
     // This is the render loop
-    *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
+    *g.borrow_mut() = Some(Closure::wrap(Box::new(move |now: f64| {
+        let mut simulation = simulation_render_loop_internal.borrow_mut();
+        let mut recording = recording_render_loop_internal.borrow_mut();
+        let wall_now = now;
+        let (now, elapsed) = match recording.as_mut() {
+            Some(active) => active.clock(now),
+            None => (now, (now - last_frame.unwrap_or(now)) as f32 / 1000.0),
+        };
+        last_frame = Some(wall_now);
+
+        let settings = *settings_render_loop_internal.borrow();
+        frame += 1;
+        let mut ensemble = ensemble_render_loop_internal.borrow_mut();
+
+        // Start or stop the worker when the setting changes, falling back to this thread if it can't start.
+        // Recordings step here so that every frame advances by the same simulated time
+        let use_worker = settings.use_worker && recording.is_none();
+        if use_worker != simulation_worker.is_some() {
+            simulation_worker = if use_worker {
+                SimulationWorker::new().map_err(|e| log!(e)).ok()
+            } else {
+                None
+            };
+        }
+        if let Some(worker) = simulation_worker.as_mut() {
+            worker.sync(&simulation, &settings, *paused.borrow());
+        }
+
         if ! *paused.borrow() {
-            // Camera related
-            let proj = Matrix4::new_perspective(1.0, 100.0, 0.1, 1000.0);
-            let camera_p = camera_pos.borrow();
-            let camera_f = camera_front.borrow();
-            let target_vector = *camera_p + *camera_f; 
-            let view = Matrix4::look_at_rh(&Point3::new(camera_p.x, camera_p.y, camera_p.z), 
-                                           &Point3::new(target_vector.x, target_vector.y, target_vector.z), 
-                                           &*camera_up.borrow());
-            let model = Matrix4::identity();
-            let mvp = proj * view * model;
-
-            context.uniform_matrix4fv_with_f32_array(modelviewprojection_uniform_location.as_ref(), false, 
-                                                     mvp.as_slice()
-                                                     );
-
-            let mut data_internal = data_render_loop_internal.borrow_mut();
-            let colours_internal = colours_render_loop_internal.borrow();
+            if settings.spawn_interval > 0 && now - *last_spawn.get_or_insert(now) >= settings.spawn_interval as f64 {
+                simulation.spawn_random();
+                last_spawn = Some(now);
+            }
+
+            let mut sweep = sweep_render_loop_internal.borrow_mut();
+            if let Some(active) = sweep.as_mut() {
+                let (value, finished) = active.advance(elapsed);
+                simulation.parameters[active.index] = value;
+                show_parameter(active.index, value);
+                set_label("sweep-label", &format!("{} = {:.3}", simulation.system.parameters[active.index].name, value));
+                if finished {
+                    *sweep = None;
+                }
+            }
+
             // Update the position of the points
-            for i in 0..data_internal.len() {
-                let new_state = data_internal[i].last().unwrap() + lorentz(*data_internal[i].last().unwrap(), *sigma.borrow(), *rho.borrow(), *beta.borrow()) * DELTA_T;
-                data_internal[i].push(new_state);
-                if data_internal[i].len() > MAX_POINTS {
-                    data_internal[i].remove(0);
+            let diverged = match simulation_worker.as_mut() {
+                Some(worker) => worker.receive(&mut simulation, settings.max_points),
+                None => simulation.step(settings.integrator, settings.delta_t, settings.max_points, settings.bounds),
+            };
+            if diverged > 0 {
+                log!(format!("removed {} diverged trajectories", diverged));
+                set_text("stability-warning", &format!(
+                    "Warning: {} trajectories diverged. The step size dt = {} is unstable for {}.",
+                    diverged, settings.delta_t, simulation.system.describe(&simulation.parameters)));
+                last_divergence = Some(now);
+            }
+
+            if let Some(ensemble) = ensemble.as_mut() {
+                if ensemble.step(simulation.system, &simulation.parameters, settings.integrator, settings.delta_t, settings.bounds) > 0 {
+                    set_label("ensemble-label", &format!("{} particles", ensemble.count()));
+                }
+            }
+
+            if density_enabled.get() {
+                let mut density = density_render_loop_internal.borrow_mut();
+                if density.is_none() {
+                    // Sized to what has been visited so far
+                    let resolution = input_element("density-resolution").value().trim().parse::<usize>().unwrap_or(256).clamp(16, 1024);
+                    *density = DensityHistogram::around(simulation.trajectories.iter().flat_map(|trajectory| trajectory.points.iter()), resolution);
+                }
+                if let Some(histogram) = density.as_mut() {
+                    histogram.add_new_states(&simulation);
+                    // Particles on the GPU would have to be read back
+                    if let Some(ActiveEnsemble::Cpu(particles)) = ensemble.as_ref() {
+                        for point in &particles.points {
+                            histogram.add(point);
+                        }
+                    }
+                    if frame.is_multiple_of(DENSITY_REDRAW_FRAMES) {
+                        let plane = Plane::parse(&select_element("density-plane").value()).unwrap_or(Plane::Xz);
+                        let canvas = html_element("density-canvas").dyn_into::<web_sys::HtmlCanvasElement>().unwrap();
+                        match histogram.render(plane, &canvas) {
+                            Ok(()) if histogram.outside > 0 => set_label("density-label", &format!("{} states, {} outside the grid", histogram.total, histogram.outside)),
+                            Ok(()) => set_label("density-label", &format!("{} states", histogram.total)),
+                            Err(e) => set_label("density-label", &e),
+                        }
+                    }
                 }
             }
+        }
+        html_element("stability-warning").set_hidden(!last_divergence.is_some_and(|time| now - time <= INSTABILITY_WARNING_DURATION));
 
-            clear(&context);
+        let mut selected = selected_render_loop_internal.borrow_mut();
+        if let Some(id) = *selected {
+            match simulation.get(id) {
+                Some(trajectory) => show_inspector(trajectory, simulation.time),
+                // Evicted or cleared
+                None => select_trajectory(&mut selected, &simulation, None),
+            }
+        }
 
-            // draw trajectories
-            for i in 0..data_internal.len(){
-                context.uniform4f(colour_uniform_location.as_ref(), colours_internal[i].x, colours_internal[i].y, colours_internal[i].z , 1.0);
-                draw_line_strip(&context, data_internal[i].as_slice());
-                //draw_square(&context, &Vector3::zeros(), &data_internal[i][data[i].len()-1], &(Matrix3::identity() * 2.0));
+        // Camera related
+        let mut camera = camera.borrow_mut();
+        // The canvas can change size without the window resizing, e.g. when the page layout changes
+        camera.aspect = resize_canvas(&canvas_render_loop_internal, &context, window().device_pixel_ratio());
+        match camera.follow {
+            Some(id) => match simulation.get(id) {
+                Some(trajectory) => {
+                    if let Some(heading) = trajectory.heading() {
+                        camera.chase(trajectory.head(), heading, elapsed);
+                    }
+                },
+                // The trajectory is gone
+                None => follow_trajectory(&mut camera, None),
+            },
+            None => camera.update(now, elapsed),
+        }
+        let model = Matrix4::identity();
+        let mvp = camera.view_projection() * model;
+
+        // Trail widths are in CSS pixels
+        let line_width = settings.line_width * window().device_pixel_ratio() as f32;
+        shaded_trails.set_view(settings.trail_style, settings.tube_radius, camera.position);
+        let renderer: &dyn TrailRenderer = match settings.trail_style {
+            TrailStyle::Lines if settings.line_width > 0.0 => &thick_lines,
+            TrailStyle::Lines => &line_strips,
+            TrailStyle::Tubes | TrailStyle::Ribbons => &shaded_trails,
+        };
+        let viewport = Vector2::new(canvas_render_loop_internal.width() as f32, canvas_render_loop_internal.height() as f32);
+        points.set_size(point_size.get() * window().device_pixel_ratio() as f32);
+        draw_scene(&context, renderer, &points, &mvp, viewport, line_width, &simulation, ensemble.as_ref(), *selected, settings.bounds);
+
+        if let Some(scale) = screenshot_render_loop_internal.borrow_mut().take() {
+            let width = canvas_render_loop_internal.width() as i32 * scale;
+            let height = canvas_render_loop_internal.height() as i32 * scale;
+            let view_projection = camera.view_projection();
+            points.set_size(point_size.get() * window().device_pixel_ratio() as f32 * scale as f32);
+            let rendered = render_offscreen(&context, width, height, |tile, tile_size| {
+                draw_scene(&context, renderer, &points, &(tile * view_projection), tile_size, line_width * scale as f32, &simulation, ensemble.as_ref(), *selected, settings.bounds);
+            });
+            match rendered {
+                Ok(image) => {
+                    save_png(&image, &format!("{}-{}x.png", simulation.system.id, scale));
+                    set_label("screenshot-label", &format!("saved {} x {} image", width, height));
+                },
+                Err(e) => set_label("screenshot-label", &e),
             }
+            context.viewport(0, 0, canvas_render_loop_internal.width() as i32, canvas_render_loop_internal.height() as i32);
+        }
 
-            // draw axes
-            context.uniform4f(colour_uniform_location.as_ref(), 1.0, 1.0, 1.0, 1.0);
-            draw_line(&context, Vector3::new(-BOUNDS, 0.0, 0.0), Vector3::new(BOUNDS, 0.0, 0.0));
-            draw_line(&context, Vector3::new(0.0, -BOUNDS, 0.0), Vector3::new(0.0, BOUNDS, 0.0));
-            draw_line(&context, Vector3::new(0.0, 0.0, -BOUNDS), Vector3::new(0.0, 0.0, BOUNDS));
+        if let Some(active) = recording.as_mut() {
+            set_label("record-label", &active.progress());
+            match active.capture(&context) {
+                Ok(false) => {},
+                Ok(true) => {
+                    recording.take().unwrap().finish(simulation.system.id);
+                    set_label("record-label", "recording finished");
+                },
+                Err(e) => {
+                    recording.take().unwrap().finish(simulation.system.id);
+                    set_label("record-label", &e);
+                },
+            }
         }
 
         // Schedule ourself for another requestAnimationFrame callback.
         request_animation_frame(f.borrow().as_ref().unwrap());
-    }) as Box<dyn FnMut()>));
-
-    let data_spawn_loop_internal = data.clone();
-    let colours_spawn_loop_internal = colours.clone();
-    spawn_local(async move {
-        let interval = IntervalStream::new(SPAWN_INTERVAL);
-        interval.for_each(|_| {
-            spawn_random_trajectory(&mut data_spawn_loop_internal.borrow_mut(), &mut colours_spawn_loop_internal.borrow_mut());
-            ready(())
-        }).await;
-    });
+    }) as Box<dyn FnMut(f64)>));
 
     request_animation_frame(g.borrow().as_ref().unwrap());
 
     // cleanup event listeners
     keydown_listener.forget();
-    rho_slider_listener.forget();
-    beta_slider_listener.forget();
-    sigma_slider_listener.forget();
+    system_select_listener.forget();
     mouse_move_listener.forget();
     canvas_click_listener.forget();
+    wheel_listener.forget();
+    fov_slider_listener.forget();
+    near_slider_listener.forget();
+    far_slider_listener.forget();
+    bookmark_select_listener.forget();
+    bookmark_save_listener.forget();
+    bookmark_delete_listener.forget();
+    restart_listener.forget();
+    follow_distance_slider_listener.forget();
+    follow_smoothing_slider_listener.forget();
+    follow_button_listener.forget();
+    unfollow_button_listener.forget();
+    turntable_button_listener.forget();
+    turntable_speed_slider_listener.forget();
+    path_record_listener.forget();
+    path_clear_listener.forget();
+    path_play_listener.forget();
+    path_save_listener.forget();
+    path_load_listener.forget();
+    settings_listener.forget();
+    distribution_listener.forget();
+    burst_listener.forget();
+    colour_scheme_listener.forget();
+    copy_link_listener.forget();
+    preset_select_listener.forget();
+    sweep_parameter_listener.forget();
+    sweep_start_listener.forget();
+    sweep_stop_listener.forget();
+    screenshot_listener.forget();
+    record_listener.forget();
+    record_stop_listener.forget();
+    ensemble_listener.forget();
+    ensemble_stop_listener.forget();
+    ensemble_check_listener.forget();
+    density_controls_listener.forget();
+    density_reset_listener.forget();
+    point_size_listener.forget();
+    export_listener.forget();
+    import_listener.forget();
+    mesh_export_listener.forget();
+    svg_export_listener.forget();
+    canvas_dragover_listener.forget();
+    canvas_drop_listener.forget();
+    preset_save_listener.forget();
+    preset_delete_listener.forget();
+    preset_export_listener.forget();
+    preset_import_listener.forget();
+    inspector_colour_listener.forget();
+    inspector_pin_listener.forget();
+    inspector_follow_listener.forget();
+    inspector_delete_listener.forget();
+    inspector_close_listener.forget();
     pause_button_listener.forget();
 
     Ok(())
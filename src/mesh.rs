@@ -0,0 +1,320 @@
+use nalgebra::{Rotation3, Vector3};
+use serde_json::json;
+
+use crate::simulation::Trajectory;
+
+#[derive(Default)]
+pub struct Mesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub colours: Vec<Vector3<f32>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+// Any unit vector perpendicular to `direction`
+fn perpendicular(direction: &Vector3<f32>) -> Vector3<f32> {
+    let axis = if direction.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+    direction.cross(&axis).normalize()
+}
+
+// Unit tangent at each point of a path, from its neighbours on either side
+pub fn tangents(path: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
+    let n = path.len();
+    (0..n)
+        .map(|i| (path[(i + 1).min(n - 1)] - path[i.saturating_sub(1)]).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z))
+        .collect()
+}
+
+// A normal at each point, carried from one tangent to the next by parallel transport so that the
+// frame neither flips nor twists along the path
+pub fn transported_normals(tangents: &[Vector3<f32>]) -> Vec<Vector3<f32>> {
+    let mut normals: Vec<Vector3<f32>> = Vec::with_capacity(tangents.len());
+    for (i, tangent) in tangents.iter().enumerate() {
+        let normal = match normals.last() {
+            None => perpendicular(tangent),
+            Some(previous) => {
+                let normal = Rotation3::rotation_between(&tangents[i - 1], tangent).map_or(*previous, |rotation| rotation * previous);
+                // Remove drift so the frame stays orthonormal
+                (normal - tangent * normal.dot(tangent)).try_normalize(f32::EPSILON).unwrap_or_else(|| perpendicular(tangent))
+            },
+        };
+        normals.push(normal);
+    }
+    normals
+}
+
+impl Mesh {
+    fn vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, colour: Vector3<f32>) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.colours.push(colour);
+        self.positions.len() as u32 - 1
+    }
+
+    // Sweep a circle of `radius` along `points`, closing both ends. Frames are carried along the
+    // curve by parallel transport so the tube doesn't twist
+    pub fn add_tube(&mut self, points: &[Vector3<f32>], radius: f32, segments: u32, colour: Vector3<f32>) {
+        // Repeated points have no direction
+        let mut path: Vec<Vector3<f32>> = Vec::with_capacity(points.len());
+        for point in points {
+            if path.last().is_none_or(|last| (point - last).norm() > f32::EPSILON) {
+                path.push(*point);
+            }
+        }
+        if path.len() < 2 || segments < 3 {
+            return;
+        }
+
+        let n = path.len();
+        let tangents = tangents(&path);
+        let normals = transported_normals(&tangents);
+        let first = self.positions.len() as u32;
+        for i in 0..n {
+            let normal = normals[i];
+            let binormal = tangents[i].cross(&normal);
+            for j in 0..segments {
+                let angle = 2.0 * std::f32::consts::PI * j as f32 / segments as f32;
+                let radial = normal * angle.cos() + binormal * angle.sin();
+                self.vertex(path[i] + radial * radius, radial, colour);
+            }
+        }
+
+        let ring = |i: u32, j: u32| first + i * segments + j % segments;
+        for i in 0..n as u32 - 1 {
+            for j in 0..segments {
+                self.triangles.push([ring(i, j), ring(i, j + 1), ring(i + 1, j + 1)]);
+                self.triangles.push([ring(i, j), ring(i + 1, j + 1), ring(i + 1, j)]);
+            }
+        }
+
+        // Flat caps get their own vertices so they can carry the cap normal
+        for (i, facing) in [(0, -tangents[0]), (n - 1, tangents[n - 1])] {
+            let centre = self.vertex(path[i], facing, colour);
+            let rim: Vec<u32> = (0..segments).map(|j| self.vertex(self.positions[ring(i as u32, j) as usize], facing, colour)).collect();
+            for j in 0..segments as usize {
+                let (a, b) = (rim[j], rim[(j + 1) % segments as usize]);
+                self.triangles.push(if i == 0 { [centre, b, a] } else { [centre, a, b] });
+            }
+        }
+    }
+
+    pub fn tubes(trajectories: &[&Trajectory], radius: f32, segments: u32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for trajectory in trajectories {
+            mesh.add_tube(&trajectory.points, radius, segments, trajectory.colour);
+        }
+        mesh
+    }
+
+    // Every stored point as an unconnected vertex
+    pub fn point_cloud(trajectories: &[&Trajectory]) -> Mesh {
+        let mut mesh = Mesh::default();
+        for trajectory in trajectories {
+            for point in &trajectory.points {
+                mesh.vertex(*point, Vector3::zeros(), trajectory.colour);
+            }
+        }
+        mesh
+    }
+
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::from("# Lorenz attractor trails\n");
+        // Vertex colours follow the position, as read by Blender and MeshLab
+        for (position, colour) in self.positions.iter().zip(&self.colours) {
+            obj.push_str(&format!("v {} {} {} {} {} {}\n", position.x, position.y, position.z, colour.x, colour.y, colour.z));
+        }
+        for normal in &self.normals {
+            obj.push_str(&format!("vn {} {} {}\n", normal.x, normal.y, normal.z));
+        }
+        for [a, b, c] in &self.triangles {
+            // OBJ indices start at 1
+            obj.push_str(&format!("f {0}//{0} {1}//{1} {2}//{2}\n", a + 1, b + 1, c + 1));
+        }
+        obj
+    }
+
+    // Binary STL has no standard way to store colours, so they are left out
+    pub fn to_stl(&self) -> Vec<u8> {
+        let mut stl = vec![0u8; 80];
+        stl.extend((self.triangles.len() as u32).to_le_bytes());
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|index| self.positions[index as usize]);
+            let normal = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::zeros);
+            for vector in [normal, a, b, c] {
+                stl.extend(vector.iter().flat_map(|component| component.to_le_bytes()));
+            }
+            stl.extend(0u16.to_le_bytes());
+        }
+        stl
+    }
+
+    // Binary little-endian PLY, faces are written only if there are any
+    pub fn to_ply(&self) -> Vec<u8> {
+        let mut header = String::from("ply\nformat binary_little_endian 1.0\ncomment Lorenz attractor trails\n");
+        header.push_str(&format!("element vertex {}\n", self.positions.len()));
+        header.push_str("property float x\nproperty float y\nproperty float z\n");
+        if !self.triangles.is_empty() {
+            header.push_str("property float nx\nproperty float ny\nproperty float nz\n");
+        }
+        header.push_str("property uchar red\nproperty uchar green\nproperty uchar blue\n");
+        if !self.triangles.is_empty() {
+            header.push_str(&format!("element face {}\nproperty list uchar uint vertex_indices\n", self.triangles.len()));
+        }
+        header.push_str("end_header\n");
+
+        let mut ply = header.into_bytes();
+        for i in 0..self.positions.len() {
+            ply.extend(self.positions[i].iter().flat_map(|component| component.to_le_bytes()));
+            if !self.triangles.is_empty() {
+                ply.extend(self.normals[i].iter().flat_map(|component| component.to_le_bytes()));
+            }
+            ply.extend(self.colours[i].iter().map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+        }
+        for triangle in &self.triangles {
+            ply.push(3);
+            ply.extend(triangle.iter().flat_map(|index| index.to_le_bytes()));
+        }
+        ply
+    }
+
+    // Single file binary glTF (.glb)
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut views = Vec::new();
+        for attribute in [&self.positions, &self.normals, &self.colours] {
+            views.push(json!({ "buffer": 0, "byteOffset": buffer.len(), "byteLength": attribute.len() * 12, "target": 34962 }));
+            buffer.extend(attribute.iter().flat_map(|vector| vector.iter().flat_map(|component| component.to_le_bytes()).collect::<Vec<u8>>()));
+        }
+        views.push(json!({ "buffer": 0, "byteOffset": buffer.len(), "byteLength": self.triangles.len() * 12, "target": 34963 }));
+        buffer.extend(self.triangles.iter().flatten().flat_map(|index| index.to_le_bytes()));
+
+        // glTF requires bounds on the position accessor
+        let mut min = Vector3::repeat(f32::MAX);
+        let mut max = Vector3::repeat(f32::MIN);
+        for position in &self.positions {
+            min = min.inf(position);
+            max = max.sup(position);
+        }
+        let count = self.positions.len();
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "Lorenz" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "NORMAL": 1, "COLOR_0": 2 }, "indices": 3 }] }],
+            "buffers": [{ "byteLength": buffer.len() }],
+            "bufferViews": views,
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": count, "type": "VEC3", "min": [min.x, min.y, min.z], "max": [max.x, max.y, max.z] },
+                { "bufferView": 1, "componentType": 5126, "count": count, "type": "VEC3" },
+                { "bufferView": 2, "componentType": 5126, "count": count, "type": "VEC3" },
+                { "bufferView": 3, "componentType": 5125, "count": self.triangles.len() * 3, "type": "SCALAR" },
+            ],
+        });
+
+        // Chunks are padded to four bytes, JSON with spaces and binary with zeros
+        let mut json = serde_json::to_vec(&document).unwrap();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(json);
+        glb.extend((buffer.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(buffer);
+        glb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    const SEGMENTS: u32 = 6;
+
+    fn tube() -> Mesh {
+        let points = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.5), Vector3::new(2.0, 1.0, 1.0)];
+        let mut mesh = Mesh::default();
+        mesh.add_tube(&points, 0.1, SEGMENTS, Vector3::new(1.0, 0.5, 0.0));
+        mesh
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn tube_has_sides_and_caps() {
+        let mesh = tube();
+        // The repeated point is skipped, leaving three sections and two caps
+        assert_eq!(mesh.triangles.len() as u32, 2 * SEGMENTS * 3 + 2 * SEGMENTS);
+        assert!(mesh.triangles.iter().flatten().all(|index| (*index as usize) < mesh.positions.len()));
+    }
+
+    #[test]
+    fn transported_normals_do_not_flip() {
+        // A tight helix around the z axis
+        let path: Vec<Vector3<f32>> = (0..200).map(|i| {
+            let angle = i as f32 * 0.05;
+            Vector3::new(0.1 * angle.cos(), 0.1 * angle.sin(), angle)
+        }).collect();
+        let tangents = tangents(&path);
+        let normals = transported_normals(&tangents);
+        for (tangent, normal) in tangents.iter().zip(&normals) {
+            assert!(tangent.dot(normal).abs() < 1e-4);
+            assert!((normal.norm() - 1.0).abs() < 1e-4);
+        }
+        assert!(normals.windows(2).all(|pair| pair[0].dot(&pair[1]) > 0.99));
+    }
+
+    #[test]
+    fn stl_holds_every_triangle() {
+        let mesh = tube();
+        let stl = mesh.to_stl();
+        assert_eq!(u32_at(&stl, 80) as usize, mesh.triangles.len());
+        // Header, count and 50 bytes per triangle
+        assert_eq!(stl.len(), 84 + 50 * mesh.triangles.len());
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned_and_consistent() {
+        let mesh = tube();
+        let glb = mesh.to_glb();
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32_at(&glb, 4), 2);
+        assert_eq!(u32_at(&glb, 8) as usize, glb.len());
+
+        let json_length = u32_at(&glb, 12) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        assert_eq!(json_length % 4, 0);
+        let document: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+
+        let bin = 20 + json_length;
+        let bin_length = u32_at(&glb, bin) as usize;
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin_length % 4, 0);
+        assert_eq!(bin + 8 + bin_length, glb.len());
+
+        let buffer_length = document["buffers"][0]["byteLength"].as_u64().unwrap() as usize;
+        assert!(buffer_length <= bin_length && bin_length - buffer_length < 4);
+        for view in document["bufferViews"].as_array().unwrap() {
+            let end = view["byteOffset"].as_u64().unwrap() + view["byteLength"].as_u64().unwrap();
+            assert!(end as usize <= buffer_length);
+        }
+        assert_eq!(document["accessors"][0]["count"].as_u64().unwrap() as usize, mesh.positions.len());
+        assert_eq!(document["accessors"][3]["count"].as_u64().unwrap() as usize, mesh.triangles.len() * 3);
+    }
+
+    #[test]
+    fn short_paths_add_nothing() {
+        let mut mesh = Mesh::default();
+        mesh.add_tube(&[Vector3::zeros(), Vector3::zeros()], 0.1, SEGMENTS, Vector3::zeros());
+        assert!(mesh.positions.is_empty() && mesh.triangles.is_empty());
+    }
+}
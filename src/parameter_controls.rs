@@ -0,0 +1,91 @@
+use gloo::events::EventListener;
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlInputElement, HtmlOptionElement};
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::dom::{document, html_element, input_element, select_element};
+use crate::simulation::Simulation;
+use crate::systems::{DynamicalSystem, SYSTEMS};
+
+pub fn populate_system_select(selected: &DynamicalSystem) {
+    let select = select_element("system-select");
+    select.set_inner_html("");
+    for system in SYSTEMS {
+        let option = HtmlOptionElement::new_with_text_and_value(system.name, system.id).unwrap();
+        select.append_child(&option).unwrap();
+    }
+    select.set_value(selected.id);
+}
+
+fn create_input(id: &str, input_type: &str) -> HtmlInputElement {
+    let input = document().create_element("input").unwrap().dyn_into::<HtmlInputElement>().unwrap();
+    input.set_id(id);
+    input.set_name(id);
+    input.set_type(input_type);
+    input
+}
+
+// Move a parameter's slider and number box to `value` without firing their listeners
+pub fn show_parameter(index: usize, value: f32) {
+    if let Some(slider) = document().get_element_by_id(&format!("param-{}-slider", index)) {
+        slider.dyn_into::<HtmlInputElement>().unwrap().set_value(&value.to_string());
+        input_element(&format!("param-{}-input", index)).set_value(&value.to_string());
+    }
+}
+
+// Build a slider and number box for every parameter of `system`. The returned listeners
+// must be kept alive for as long as the controls are on the page
+pub fn build_parameter_controls(system: &DynamicalSystem, values: &[f32], simulation: &Arc<RefCell<Simulation>>) -> Vec<EventListener> {
+    let container = html_element("parameter-controls");
+    container.set_inner_html("");
+    let mut listeners = Vec::new();
+
+    for (index, (parameter, value)) in system.parameters.iter().zip(values).enumerate() {
+        let row = document().create_element("div").unwrap();
+
+        let slider_id = format!("param-{}-slider", index);
+        let input_id = format!("param-{}-input", index);
+
+        let label = document().create_element("label").unwrap();
+        label.set_attribute("for", &slider_id).unwrap();
+        label.set_text_content(Some(parameter.name));
+
+        let slider = create_input(&slider_id, "range");
+        slider.set_class_name("slider");
+        slider.set_min(&parameter.min.to_string());
+        slider.set_max(&parameter.max.to_string());
+        slider.set_step(&parameter.step.to_string());
+
+        let number = create_input(&input_id, "number");
+        number.set_step("any");
+
+        row.append_child(&label).unwrap();
+        row.append_child(&slider).unwrap();
+        row.append_child(&number).unwrap();
+        container.append_child(&row).unwrap();
+        show_parameter(index, *value);
+
+        let simulation_slider_internal = simulation.clone();
+        listeners.push(EventListener::new(&slider, "input", move |_event| {
+            let value = input_element(&format!("param-{}-slider", index)).value();
+            if let Ok(value) = value.parse::<f32>() {
+                simulation_slider_internal.borrow_mut().parameters[index] = value;
+                input_element(&format!("param-{}-input", index)).set_value(&value.to_string());
+            }
+        }));
+
+        // The number box accepts values outside the slider's range
+        let simulation_number_internal = simulation.clone();
+        listeners.push(EventListener::new(&number, "input", move |_event| {
+            let value = input_element(&format!("param-{}-input", index)).value();
+            if let Some(value) = value.parse::<f32>().ok().filter(|value| value.is_finite()) {
+                simulation_number_internal.borrow_mut().parameters[index] = value;
+                input_element(&format!("param-{}-slider", index)).set_value(&value.to_string());
+            }
+        }));
+    }
+
+    listeners
+}
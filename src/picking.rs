@@ -0,0 +1,197 @@
+use nalgebra::{Matrix4, Vector2, Vector3};
+
+use crate::simulation::Trajectory;
+
+#[derive(Clone, Copy, Debug)]
+pub enum SpawnTarget {
+    // Plane perpendicular to the given axis (0 = x, 1 = y, 2 = z) at an offset along it
+    Plane { axis: usize, offset: f32 },
+    // Fixed positive distance along the ray
+    Depth(f32),
+}
+
+impl SpawnTarget {
+    pub fn parse(target: &str, offset: f32) -> Option<Self> {
+        match target {
+            "yz" => Some(SpawnTarget::Plane { axis: 0, offset }),
+            "xz" => Some(SpawnTarget::Plane { axis: 1, offset }),
+            "xy" => Some(SpawnTarget::Plane { axis: 2, offset }),
+            // A depth of zero or less would spawn at or behind the camera
+            "depth" if offset > 0.0 => Some(SpawnTarget::Depth(offset)),
+            _ => None,
+        }
+    }
+}
+
+// Map a position in CSS pixels on an element to normalised device coordinates
+pub fn to_ndc(x: f32, y: f32, width: f32, height: f32) -> Vector2<f32> {
+    Vector2::new(2.0 * x / width - 1.0, 1.0 - 2.0 * y / height)
+}
+
+pub fn intersect(origin: Vector3<f32>, direction: Vector3<f32>, target: SpawnTarget) -> Option<Vector3<f32>> {
+    match target {
+        SpawnTarget::Plane { axis, offset } => {
+            if direction[axis].abs() < f32::EPSILON {
+                return None;
+            }
+            let distance = (offset - origin[axis]) / direction[axis];
+            // Only hit planes in front of the camera
+            if distance > 0.0 {
+                Some(origin + direction * distance)
+            } else {
+                None
+            }
+        },
+        SpawnTarget::Depth(depth) => Some(origin + direction * depth),
+    }
+}
+
+// Project a world point to CSS pixels on an element, None if it is behind the camera
+pub fn project(mvp: &Matrix4<f32>, point: &Vector3<f32>, width: f32, height: f32) -> Option<Vector2<f32>> {
+    let clip = mvp * point.push(1.0);
+    if clip.w <= f32::EPSILON {
+        return None;
+    }
+    Some(Vector2::new((clip.x / clip.w + 1.0) * 0.5 * width, (1.0 - clip.y / clip.w) * 0.5 * height))
+}
+
+fn distance_to_segment(p: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    let ab = b - a;
+    let length_squared = ab.norm_squared();
+    let t = if length_squared > 0.0 { ((p - a).dot(&ab) / length_squared).clamp(0.0, 1.0) } else { 0.0 };
+    (p - (a + ab * t)).norm()
+}
+
+// Id of the trajectory whose on-screen trail passes closest to `cursor`, within `radius` pixels
+pub fn nearest_trajectory(trajectories: &[Trajectory], mvp: &Matrix4<f32>, cursor: Vector2<f32>, width: f32, height: f32, radius: f32) -> Option<u32> {
+    let mut nearest = None;
+    let mut nearest_distance = radius;
+    for trajectory in trajectories {
+        let projected: Vec<Option<Vector2<f32>>> = trajectory.points.iter().map(|point| project(mvp, point, width, height)).collect();
+        let distances = projected.windows(2).filter_map(|segment| match segment {
+            [Some(a), Some(b)] => Some(distance_to_segment(cursor, *a, *b)),
+            _ => None,
+        });
+        // A trajectory with a single point is picked by that point alone
+        let distance = match projected.as_slice() {
+            [Some(point)] => Some((cursor - point).norm()),
+            _ => distances.reduce(f32::min),
+        };
+        if let Some(distance) = distance.filter(|distance| *distance <= nearest_distance) {
+            nearest_distance = distance;
+            nearest = Some(trajectory.id);
+        }
+    }
+    nearest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Perspective3, Point3};
+
+    use crate::distributions::InitialDistribution;
+    use crate::simulation::Simulation;
+    use crate::systems::SYSTEMS;
+
+    // Camera at z = 10 looking at the origin, on a 100 by 100 pixel canvas
+    fn view_projection() -> Matrix4<f32> {
+        let view = Matrix4::look_at_rh(&Point3::new(0.0, 0.0, 10.0), &Point3::origin(), &Vector3::y());
+        Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0).to_homogeneous() * view
+    }
+
+    fn trails(trails: &[&[[f32; 3]]]) -> Simulation {
+        let mut simulation = Simulation::new(&SYSTEMS[0], 10, InitialDistribution::cube(1.0), 0);
+        for trail in trails {
+            let points: Vec<Vector3<f32>> = trail.iter().map(|p| Vector3::from(*p)).collect();
+            let times = vec![0.0; points.len()];
+            simulation.add_static(points, times, Vector3::zeros());
+        }
+        simulation
+    }
+
+    fn pick(simulation: &Simulation, x: f32, y: f32, radius: f32) -> Option<u32> {
+        nearest_trajectory(&simulation.trajectories, &view_projection(), Vector2::new(x, y), 100.0, 100.0, radius)
+    }
+
+    #[test]
+    fn ndc_spans_the_element() {
+        assert_eq!(to_ndc(0.0, 0.0, 200.0, 100.0), Vector2::new(-1.0, 1.0));
+        assert_eq!(to_ndc(100.0, 50.0, 200.0, 100.0), Vector2::new(0.0, 0.0));
+        assert_eq!(to_ndc(200.0, 100.0, 200.0, 100.0), Vector2::new(1.0, -1.0));
+    }
+
+    #[test]
+    fn rays_hit_planes_in_front_of_the_camera() {
+        let origin = Vector3::new(0.0, 0.0, 10.0);
+        let target = SpawnTarget::parse("xy", 2.0).unwrap();
+        let hit = intersect(origin, Vector3::new(1.0, 0.0, -1.0), target).unwrap();
+        assert!((hit - Vector3::new(8.0, 0.0, 2.0)).norm() < 1e-5);
+        // Parallel to the plane
+        assert!(intersect(origin, Vector3::new(1.0, 0.0, 0.0), target).is_none());
+        // The plane is behind the camera
+        assert!(intersect(origin, Vector3::new(0.0, 0.0, 1.0), target).is_none());
+    }
+
+    #[test]
+    fn depth_must_be_positive() {
+        assert!(SpawnTarget::parse("depth", 0.0).is_none());
+        assert!(SpawnTarget::parse("depth", -5.0).is_none());
+        assert!(SpawnTarget::parse("depth", f32::NAN).is_none());
+        let target = SpawnTarget::parse("depth", 5.0).unwrap();
+        let hit = intersect(Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), target).unwrap();
+        assert_eq!(hit, Vector3::new(1.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn points_behind_the_camera_are_not_projected() {
+        let mvp = view_projection();
+        let centre = project(&mvp, &Vector3::zeros(), 100.0, 100.0).unwrap();
+        assert!((centre - Vector2::new(50.0, 50.0)).norm() < 1e-4);
+        assert!(project(&mvp, &Vector3::new(0.0, 0.0, 20.0), 100.0, 100.0).is_none());
+    }
+
+    #[test]
+    fn distance_is_measured_to_the_closest_point_of_a_segment() {
+        let (a, b) = (Vector2::new(0.0, 0.0), Vector2::new(10.0, 0.0));
+        assert_eq!(distance_to_segment(Vector2::new(5.0, 3.0), a, b), 3.0);
+        assert_eq!(distance_to_segment(Vector2::new(13.0, 4.0), a, b), 5.0);
+        // A degenerate segment is a point
+        assert_eq!(distance_to_segment(Vector2::new(3.0, 4.0), a, a), 5.0);
+    }
+
+    #[test]
+    fn trails_beyond_the_radius_are_not_picked() {
+        // The trail runs along y = 0, which is the middle row of the canvas
+        let simulation = trails(&[&[[-5.0, 0.0, 0.0], [5.0, 0.0, 0.0]]]);
+        let id = simulation.trajectories[0].id;
+        assert_eq!(pick(&simulation, 50.0, 55.0, 8.0), Some(id));
+        assert_eq!(pick(&simulation, 50.0, 60.0, 8.0), None);
+    }
+
+    #[test]
+    fn segments_behind_the_camera_are_ignored() {
+        // Seen from the camera the segment would cross the cursor, but one end is behind it
+        let simulation = trails(&[&[[0.0, 0.0, 20.0], [0.0, 0.0, 0.0]]]);
+        assert_eq!(pick(&simulation, 50.0, 50.0, 8.0), None);
+    }
+
+    #[test]
+    fn single_points_are_picked() {
+        let simulation = trails(&[&[[0.0, 0.0, 0.0]]]);
+        let id = simulation.trajectories[0].id;
+        assert_eq!(pick(&simulation, 53.0, 54.0, 8.0), Some(id));
+        assert_eq!(pick(&simulation, 60.0, 60.0, 8.0), None);
+    }
+
+    #[test]
+    fn the_closer_trail_is_picked() {
+        let simulation = trails(&[
+            &[[-5.0, 0.5, 0.0], [5.0, 0.5, 0.0]],
+            &[[-5.0, -0.2, 0.0], [5.0, -0.2, 0.0]],
+        ]);
+        let (far, near) = (simulation.trajectories[0].id, simulation.trajectories[1].id);
+        assert_eq!(pick(&simulation, 50.0, 50.0, 8.0), Some(near));
+        assert_eq!(pick(&simulation, 50.0, 46.0, 8.0), Some(far));
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use web_sys::HtmlOptionElement;
+
+use crate::dom::{local_storage, select_element, set_label};
+use crate::systems::{DynamicalSystem, find_system};
+
+// localStorage key holding the user's presets
+const STORAGE_KEY: &str = "parameter-presets";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Preset {
+    pub name: String,
+    // Id of the system the parameters belong to
+    pub system: String,
+    pub parameters: Vec<f32>,
+    pub description: String,
+    // Built-in presets can't be deleted and aren't saved or exported
+    #[serde(skip)]
+    pub builtin: bool,
+}
+
+impl Preset {
+    fn builtin(name: &str, system: &str, parameters: &[f32], description: &str) -> Self {
+        Preset {
+            name: name.to_string(),
+            system: system.to_string(),
+            parameters: parameters.to_vec(),
+            description: description.to_string(),
+            builtin: true,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let system = find_system(&self.system).ok_or_else(|| format!("preset {} uses unknown system {}", self.name, self.system))?;
+        if self.parameters.len() != system.parameters.len() {
+            return Err(format!("preset {} needs {} parameters", self.name, system.parameters.len()));
+        }
+        if self.parameters.iter().any(|value| !value.is_finite()) {
+            return Err(format!("preset {} has a parameter that isn't a number", self.name));
+        }
+        Ok(())
+    }
+}
+
+pub fn builtin_presets() -> Vec<Preset> {
+    const BETA: f32 = 8.0 / 3.0;
+    vec![
+        Preset::builtin("classic chaos", "lorenz", &[10.0, 28.0, BETA],
+            "Lorenz's original values, the butterfly-shaped strange attractor."),
+        Preset::builtin("periodic window (rho = 99.96)", "lorenz", &[10.0, 99.96, BETA],
+            "Chaos gives way to a stable periodic orbit inside a window of rho around 100."),
+        Preset::builtin("periodic window (rho = 160)", "lorenz", &[10.0, 160.0, BETA],
+            "Another window of periodic behaviour, trajectories settle onto a single closed loop."),
+        Preset::builtin("pre-turbulence (rho = 23)", "lorenz", &[10.0, 23.0, BETA],
+            "Transient chaos: trajectories wander chaotically for a while before spiralling into a fixed point."),
+        Preset::builtin("stable spirals (rho = 15)", "lorenz", &[10.0, 15.0, BETA],
+            "Below rho = 24.74 the two fixed points are stable and every trajectory eventually spirals into one of them."),
+        Preset::builtin("trefoil orbit", "lorenz", &[10.0, 99.96, BETA],
+            "The periodic orbit at rho = 99.96 is knotted as a trefoil, a (3, 2) torus knot. Use a long trail to see it."),
+    ]
+}
+
+pub fn load_user_presets() -> Vec<Preset> {
+    let text = local_storage().and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten());
+    match text.map(|text| presets_from_json(&text)) {
+        Some(Ok(presets)) => presets,
+        Some(Err(e)) => {
+            set_label("preset-description", &format!("Saved presets could not be loaded: {}", e));
+            Vec::new()
+        },
+        None => Vec::new(),
+    }
+}
+
+// Store the user's presets, the built-in ones are left out
+pub fn save_user_presets(presets: &[Preset]) {
+    if let Some(storage) = local_storage() {
+        if storage.set_item(STORAGE_KEY, &presets_to_json(presets)).is_err() {
+            set_label("preset-description", "Presets could not be saved");
+        }
+    }
+}
+
+pub fn presets_to_json(presets: &[Preset]) -> String {
+    let user: Vec<&Preset> = presets.iter().filter(|preset| !preset.builtin).collect();
+    serde_json::to_string_pretty(&user).unwrap()
+}
+
+pub fn presets_from_json(text: &str) -> Result<Vec<Preset>, String> {
+    let presets: Vec<Preset> = serde_json::from_str(text).map_err(|e| format!("Invalid presets: {}", e))?;
+    for preset in &presets {
+        preset.validate()?;
+    }
+    Ok(presets)
+}
+
+// Only the presets for `system` are listed, option values index into `presets`
+pub fn refresh_preset_select(presets: &[Preset], system: &DynamicalSystem) {
+    let select = select_element("preset-select");
+    select.set_inner_html("");
+    let placeholder = HtmlOptionElement::new_with_text_and_value("choose a preset", "").unwrap();
+    select.append_child(&placeholder).unwrap();
+    for (i, preset) in presets.iter().enumerate().filter(|(_, preset)| preset.system == system.id) {
+        let option = HtmlOptionElement::new_with_text_and_value(&preset.name, &i.to_string()).unwrap();
+        select.append_child(&option).unwrap();
+    }
+    set_label("preset-description", "");
+}
@@ -0,0 +1,157 @@
+use gloo::events::EventListener;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BlobEvent, CanvasCaptureMediaStreamTrack, HtmlCanvasElement, MediaRecorder, MediaRecorderOptions, WebGl2RenderingContext};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::dom::{download_blob, download_bytes};
+use crate::gif::GifEncoder;
+
+// GIFs wider than this are downscaled by a whole factor
+const GIF_MAX_WIDTH: u32 = 640;
+
+enum Output {
+    // MediaRecorder stamps each frame with the wall time it was requested at, not with our
+    // clock. Every frame still advances the animation by 1 / fps, but the video plays back at
+    // the speed the frames were drawn, so a slow machine gives a slow-motion WebM
+    WebM {
+        recorder: MediaRecorder,
+        track: CanvasCaptureMediaStreamTrack,
+        chunks: Rc<RefCell<Vec<JsValue>>>,
+    },
+    Gif {
+        encoder: GifEncoder,
+        // Size of the canvas the frames are read from, the recording stops if it changes
+        source_width: u32,
+        source_height: u32,
+        downscale: u32,
+    },
+}
+
+// Captures a fixed number of frames. While it runs the render loop takes its clock from
+// `clock` instead of the wall, so every frame covers the same slice of animation however
+// long the browser takes to produce it
+pub struct Recording {
+    fps: u32,
+    frames: u32,
+    captured: u32,
+    // Wall time in milliseconds of the first frame, set on the first call to `clock`
+    start: Option<f64>,
+    output: Output,
+}
+
+impl Recording {
+    pub fn webm(canvas: &HtmlCanvasElement, fps: u32, seconds: f32) -> Result<Self, String> {
+        // A frame rate of 0 means frames are only captured when we ask for them
+        let stream = canvas.capture_stream_with_frame_request_rate(0.0).map_err(|e| format!("Could not capture the canvas: {:?}", e))?;
+        let track = stream
+            .get_video_tracks()
+            .get(0)
+            .dyn_into::<CanvasCaptureMediaStreamTrack>()
+            .map_err(|_| String::from("Canvas capture is not supported"))?;
+        let options = MediaRecorderOptions::new();
+        options.set_mime_type("video/webm");
+        let recorder = MediaRecorder::new_with_media_stream_and_media_recorder_options(&stream, &options)
+            .map_err(|e| format!("WebM recording is not supported: {:?}", e))?;
+
+        let chunks: Rc<RefCell<Vec<JsValue>>> = Default::default();
+        let chunks_internal = chunks.clone();
+        EventListener::new(&recorder, "dataavailable", move |event| {
+            if let Some(data) = event.dyn_ref::<BlobEvent>().and_then(|event| event.data()) {
+                chunks_internal.borrow_mut().push(data.into());
+            }
+        }).forget();
+        recorder.start().map_err(|e| format!("Could not start recording: {:?}", e))?;
+
+        Ok(Recording { fps, frames: (seconds * fps as f32).round() as u32, captured: 0, start: None, output: Output::WebM { recorder, track, chunks } })
+    }
+
+    pub fn gif(canvas: &HtmlCanvasElement, fps: u32, seconds: f32) -> Self {
+        let (source_width, source_height) = (canvas.width(), canvas.height());
+        let downscale = source_width.div_ceil(GIF_MAX_WIDTH).max(1);
+        let encoder = GifEncoder::new((source_width / downscale) as u16, (source_height / downscale) as u16);
+        Recording {
+            fps,
+            frames: (seconds * fps as f32).round() as u32,
+            captured: 0,
+            start: None,
+            output: Output::Gif { encoder, source_width, source_height, downscale },
+        }
+    }
+
+    // Time in milliseconds and seconds since the last frame, as seen by the frame about to be drawn
+    pub fn clock(&mut self, now: f64) -> (f64, f32) {
+        let start = *self.start.get_or_insert(now);
+        (start + self.captured as f64 * 1000.0 / self.fps as f64, 1.0 / self.fps as f32)
+    }
+
+    pub fn progress(&self) -> String {
+        format!("recording frame {} of {}", self.captured + 1, self.frames)
+    }
+
+    // Grab the frame just drawn, returns true once every frame has been captured. After an
+    // error the frames captured so far can still be saved with `finish`
+    pub fn capture(&mut self, context: &WebGl2RenderingContext) -> Result<bool, String> {
+        let fps = self.fps;
+        let index = self.captured;
+        match &mut self.output {
+            Output::WebM { track, .. } => track.request_frame(),
+            Output::Gif { encoder, source_width, source_height, downscale } => {
+                let (width, height) = (*source_width as i32, *source_height as i32);
+                if context.drawing_buffer_width() != width || context.drawing_buffer_height() != height {
+                    return Err(String::from("the canvas was resized, recording stopped"));
+                }
+                let mut pixels = vec![0u8; (width * height * 4) as usize];
+                context
+                    .read_pixels_with_opt_u8_array(0, 0, width, height, WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE, Some(&mut pixels))
+                    .map_err(|e| format!("Could not read pixels: {:?}", e))?;
+                let frame = shrink(&pixels, *source_width, *source_height, *downscale);
+                // Delays are whole hundredths of a second, spread the rounding across frames
+                let delay = (((index + 1) * 100) as f32 / fps as f32).round() - ((index * 100) as f32 / fps as f32).round();
+                encoder.add_frame(&frame, delay as u16);
+            },
+        }
+        self.captured += 1;
+        Ok(self.captured >= self.frames)
+    }
+
+    pub fn finish(self, name: &str) {
+        match self.output {
+            Output::WebM { recorder, chunks, .. } => {
+                let filename = format!("{}.webm", name);
+                EventListener::once(&recorder, "stop", move |_event| {
+                    let parts: js_sys::Array = chunks.borrow().iter().collect();
+                    let options = web_sys::BlobPropertyBag::new();
+                    options.set_type("video/webm");
+                    if let Ok(blob) = web_sys::Blob::new_with_blob_sequence_and_options(&parts, &options) {
+                        download_blob(&filename, &blob);
+                    }
+                }).forget();
+                let _ = recorder.stop();
+            },
+            Output::Gif { encoder, .. } => download_bytes(&format!("{}.gif", name), "image/gif", &encoder.finish()),
+        }
+    }
+}
+
+// Average `factor` x `factor` blocks of a bottom-up RGBA image into a smaller top-down one
+fn shrink(pixels: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    let (out_width, out_height) = (width / factor, height / factor);
+    let mut out = Vec::with_capacity((out_width * out_height * 4) as usize);
+    for y in (0..out_height).rev() {
+        for x in 0..out_width {
+            let mut sum = [0u32; 4];
+            for dy in 0..factor {
+                for dx in 0..factor {
+                    let i = (((y * factor + dy) * width + x * factor + dx) * 4) as usize;
+                    for channel in 0..4 {
+                        sum[channel] += pixels[i + channel] as u32;
+                    }
+                }
+            }
+            out.extend(sum.iter().map(|total| (total / (factor * factor)) as u8));
+        }
+    }
+    out
+}
@@ -0,0 +1,159 @@
+use nalgebra::Vector3;
+
+use crate::camera::CameraPose;
+use crate::colour::ColourScheme;
+use crate::integrators::Integrator;
+use crate::systems::{DynamicalSystem, find_system};
+
+// Everything needed to reproduce a scene, shared as the URL fragment
+#[derive(Clone)]
+pub struct Scene {
+    pub system: &'static DynamicalSystem,
+    pub parameters: Vec<f32>,
+    pub integrator: Integrator,
+    pub delta_t: f32,
+    pub seed: u32,
+    pub camera: CameraPose,
+    pub colour_scheme: ColourScheme,
+    pub max_points: usize,
+}
+
+fn numbers(text: &str, name: &str) -> Result<Vec<f32>, String> {
+    text.split(',')
+        .map(|value| value.parse::<f32>().ok().filter(|value| value.is_finite()))
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| format!("{} must be a list of numbers", name))
+}
+
+impl Scene {
+    pub fn to_fragment(&self) -> String {
+        let pose = &self.camera;
+        let parameters = self.parameters.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(",");
+        format!("system={}&params={}&integrator={}&dt={}&seed={}&camera={},{},{},{},{},{}&colours={}&trail={}",
+            self.system.id, parameters, self.integrator.id(), self.delta_t, self.seed,
+            pose.position.x, pose.position.y, pose.position.z, pose.yaw, pose.pitch, pose.fov,
+            self.colour_scheme.encode(), self.max_points)
+    }
+
+    // Keys missing from the fragment keep their value from `base`
+    pub fn from_fragment(fragment: &str, base: Scene) -> Result<Scene, String> {
+        let fragment = fragment.strip_prefix('#').unwrap_or(fragment);
+        let mut scene = base;
+        let mut parameters = None;
+        for pair in fragment.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got {}", pair))?;
+            match key {
+                "system" => {
+                    scene.system = find_system(value).ok_or_else(|| format!("unknown system {}", value))?;
+                    scene.parameters = scene.system.defaults();
+                },
+                "params" => parameters = Some(numbers(value, "params")?),
+                "integrator" => scene.integrator = Integrator::parse(value).ok_or_else(|| format!("unknown integrator {}", value))?,
+                "dt" => scene.delta_t = value.parse().ok().filter(|dt: &f32| dt.is_finite() && *dt > 0.0).ok_or("dt must be a positive number")?,
+                "seed" => scene.seed = value.parse().map_err(|_| "seed must be a whole number")?,
+                "camera" => match numbers(value, "camera")?[..] {
+                    [x, y, z, yaw, pitch, fov] => scene.camera = CameraPose { position: Vector3::new(x, y, z), yaw, pitch, fov },
+                    _ => return Err(String::from("camera needs x, y, z, yaw, pitch and fov")),
+                },
+                "colours" => scene.colour_scheme = ColourScheme::decode(value).ok_or_else(|| format!("unknown colour scheme {}", value))?,
+                "trail" => scene.max_points = value.parse().ok().filter(|points| *points >= 2).ok_or("trail must be at least 2 points")?,
+                // Ignore keys from newer versions
+                _ => {},
+            }
+        }
+        // Parameters are applied last so they don't depend on the order of the keys
+        if let Some(parameters) = parameters {
+            if parameters.len() != scene.system.parameters.len() {
+                return Err(format!("{} takes {} parameters", scene.system.name, scene.system.parameters.len()));
+            }
+            scene.parameters = parameters;
+        }
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lorenz() -> Scene {
+        let system = find_system("lorenz").unwrap();
+        Scene {
+            system,
+            parameters: system.defaults(),
+            integrator: Integrator::Euler,
+            delta_t: 0.002,
+            seed: 0,
+            camera: CameraPose { position: Vector3::new(0.0, 0.0, 500.0), yaw: -90.0, pitch: 0.0, fov: 45.0 },
+            colour_scheme: ColourScheme::Random,
+            max_points: 500,
+        }
+    }
+
+    fn error(fragment: &str) -> String {
+        match Scene::from_fragment(fragment, lorenz()) {
+            Ok(_) => panic!("{} was accepted", fragment),
+            Err(e) => e,
+        }
+    }
+
+    fn assert_same(a: &Scene, b: &Scene) {
+        assert_eq!(a.system.id, b.system.id);
+        assert_eq!(a.parameters, b.parameters);
+        assert_eq!(a.integrator, b.integrator);
+        assert_eq!(a.delta_t, b.delta_t);
+        assert_eq!(a.seed, b.seed);
+        assert_eq!(a.camera.position, b.camera.position);
+        assert_eq!((a.camera.yaw, a.camera.pitch, a.camera.fov), (b.camera.yaw, b.camera.pitch, b.camera.fov));
+        assert_eq!(a.colour_scheme, b.colour_scheme);
+        assert_eq!(a.max_points, b.max_points);
+    }
+
+    #[test]
+    fn fragment_round_trip_is_exact() {
+        let system = find_system("chen").unwrap();
+        let scene = Scene {
+            system,
+            // Values without a short decimal form check that nothing is rounded
+            parameters: vec![35.1, 1.0 / 3.0, 28.000_002],
+            integrator: Integrator::Rk4,
+            delta_t: 0.001_7,
+            seed: 4_000_000_000,
+            camera: CameraPose { position: Vector3::new(-12.5, 0.1, 1e-7), yaw: 123.456, pitch: -89.0, fov: 30.5 },
+            colour_scheme: ColourScheme::Single(Vector3::new(1.0, 0.0, 1.0)),
+            max_points: 12_345,
+        };
+        let restored = Scene::from_fragment(&format!("#{}", scene.to_fragment()), lorenz()).unwrap();
+        assert_same(&restored, &scene);
+    }
+
+    #[test]
+    fn missing_keys_keep_the_base() {
+        assert_same(&Scene::from_fragment("", lorenz()).unwrap(), &lorenz());
+        let scene = Scene::from_fragment("seed=7&unknown=1", lorenz()).unwrap();
+        assert_eq!(scene.seed, 7);
+        assert_eq!(scene.delta_t, 0.002);
+        // A new system without parameters starts from its defaults
+        let scene = Scene::from_fragment("system=thomas", lorenz()).unwrap();
+        assert_eq!(scene.parameters, find_system("thomas").unwrap().defaults());
+    }
+
+    #[test]
+    fn parameters_must_match_the_system() {
+        assert_eq!(error("params=1,2"), "Lorenz takes 3 parameters");
+        // Checked against the system named anywhere in the fragment
+        assert_eq!(error("params=1,2,3&system=thomas"), "Thomas takes 1 parameters");
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        assert_eq!(error("system=duffing"), "unknown system duffing");
+        assert_eq!(error("params=10,abc,2"), "params must be a list of numbers");
+        assert_eq!(error("params=10,NaN,2"), "params must be a list of numbers");
+        assert_eq!(error("camera=1,2,3"), "camera needs x, y, z, yaw, pitch and fov");
+        assert_eq!(error("dt=-0.1"), "dt must be a positive number");
+        assert_eq!(error("seed=1.5"), "seed must be a whole number");
+        assert_eq!(error("trail=1"), "trail must be at least 2 points");
+        assert_eq!(error("seed"), "expected key=value, got seed");
+    }
+}
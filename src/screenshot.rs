@@ -0,0 +1,154 @@
+use nalgebra::{Matrix4, Vector2};
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData, WebGl2RenderingContext};
+
+use crate::dom::{document, download_blob};
+
+// Upper bound on the tile size even when the driver allows larger renderbuffers
+const MAX_TILE_SIZE: i32 = 4096;
+
+// Maps the part of normalised device coordinates covered by the pixel rectangle
+// [x0, x1) x [y0, y1) of a width x height image onto the whole viewport
+pub fn tile_projection(x0: i32, y0: i32, x1: i32, y1: i32, width: i32, height: i32) -> Matrix4<f32> {
+    let (left, right) = (2.0 * x0 as f32 / width as f32 - 1.0, 2.0 * x1 as f32 / width as f32 - 1.0);
+    let (bottom, top) = (2.0 * y0 as f32 / height as f32 - 1.0, 2.0 * y1 as f32 / height as f32 - 1.0);
+    let mut tile = Matrix4::identity();
+    tile[(0, 0)] = 2.0 / (right - left);
+    tile[(0, 3)] = -(right + left) / (right - left);
+    tile[(1, 1)] = 2.0 / (top - bottom);
+    tile[(1, 3)] = -(top + bottom) / (top - bottom);
+    tile
+}
+
+fn tile_size(context: &WebGl2RenderingContext) -> i32 {
+    let max_renderbuffer = context
+        .get_parameter(WebGl2RenderingContext::MAX_RENDERBUFFER_SIZE)
+        .ok()
+        .and_then(|value| value.as_f64())
+        .unwrap_or(1024.0) as i32;
+    max_renderbuffer.min(MAX_TILE_SIZE)
+}
+
+// Render a width x height image into a new 2D canvas. The image is split into tiles no larger than
+// the biggest renderbuffer, `draw` is called once per tile with the matrix to apply after the projection
+// and the size of the tile in pixels
+pub fn render_offscreen<F: FnMut(&Matrix4<f32>, Vector2<f32>)>(context: &WebGl2RenderingContext, width: i32, height: i32, mut draw: F) -> Result<HtmlCanvasElement, String> {
+    let output = document().create_element("canvas").unwrap().dyn_into::<HtmlCanvasElement>().unwrap();
+    output.set_width(width as u32);
+    output.set_height(height as u32);
+    let output_context = output
+        .get_context("2d")
+        .ok()
+        .flatten()
+        .ok_or("Could not create a 2D canvas for the screenshot")?
+        .dyn_into::<CanvasRenderingContext2d>()
+        .unwrap();
+
+    let size = tile_size(context);
+    let framebuffer = context.create_framebuffer().ok_or("Could not create framebuffer")?;
+    let renderbuffer = context.create_renderbuffer().ok_or("Could not create renderbuffer")?;
+    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+    context.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, Some(&renderbuffer));
+    context.renderbuffer_storage(WebGl2RenderingContext::RENDERBUFFER, WebGl2RenderingContext::RGBA8, size.min(width), size.min(height));
+    context.framebuffer_renderbuffer(WebGl2RenderingContext::FRAMEBUFFER, WebGl2RenderingContext::COLOR_ATTACHMENT0, WebGl2RenderingContext::RENDERBUFFER, Some(&renderbuffer));
+    let depthbuffer = context.create_renderbuffer().ok_or("Could not create renderbuffer")?;
+    context.bind_renderbuffer(WebGl2RenderingContext::RENDERBUFFER, Some(&depthbuffer));
+    context.renderbuffer_storage(WebGl2RenderingContext::RENDERBUFFER, WebGl2RenderingContext::DEPTH_COMPONENT24, size.min(width), size.min(height));
+    context.framebuffer_renderbuffer(WebGl2RenderingContext::FRAMEBUFFER, WebGl2RenderingContext::DEPTH_ATTACHMENT, WebGl2RenderingContext::RENDERBUFFER, Some(&depthbuffer));
+
+    let mut result = Ok(());
+    if context.check_framebuffer_status(WebGl2RenderingContext::FRAMEBUFFER) != WebGl2RenderingContext::FRAMEBUFFER_COMPLETE {
+        result = Err(String::from("Offscreen framebuffer is incomplete"));
+    }
+
+    let mut y0 = 0;
+    while result.is_ok() && y0 < height {
+        let y1 = (y0 + size).min(height);
+        let mut x0 = 0;
+        while result.is_ok() && x0 < width {
+            let x1 = (x0 + size).min(width);
+            let (tile_width, tile_height) = (x1 - x0, y1 - y0);
+            context.viewport(0, 0, tile_width, tile_height);
+            draw(&tile_projection(x0, y0, x1, y1, width, height), Vector2::new(tile_width as f32, tile_height as f32));
+
+            let mut pixels = vec![0u8; (tile_width * tile_height * 4) as usize];
+            result = context
+                .read_pixels_with_opt_u8_array(0, 0, tile_width, tile_height, WebGl2RenderingContext::RGBA, WebGl2RenderingContext::UNSIGNED_BYTE, Some(&mut pixels))
+                .map_err(|e| format!("Could not read pixels: {:?}", e))
+                .and_then(|_| {
+                    // GL rows start at the bottom, canvas rows at the top
+                    let row = (tile_width * 4) as usize;
+                    let flipped: Vec<u8> = pixels.chunks(row).rev().flatten().copied().collect();
+                    let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&flipped), tile_width as u32, tile_height as u32)
+                        .map_err(|e| format!("Could not create image: {:?}", e))?;
+                    output_context
+                        .put_image_data(&image, x0 as f64, (height - y1) as f64)
+                        .map_err(|e| format!("Could not copy tile: {:?}", e))
+                });
+            x0 = x1;
+        }
+        y0 = y1;
+    }
+
+    context.bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+    context.delete_renderbuffer(Some(&renderbuffer));
+    context.delete_renderbuffer(Some(&depthbuffer));
+    context.delete_framebuffer(Some(&framebuffer));
+    result.map(|_| output)
+}
+
+pub fn save_png(canvas: &HtmlCanvasElement, filename: &str) {
+    let filename = filename.to_string();
+    let callback = Closure::once_into_js(move |blob: JsValue| {
+        if let Ok(blob) = blob.dyn_into::<web_sys::Blob>() {
+            download_blob(&filename, &blob);
+        }
+    });
+    canvas.to_blob(callback.unchecked_ref()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector4;
+
+    use super::*;
+
+    // Normalised device coordinates of a pixel position in a width x height target
+    fn ndc(x: f32, y: f32, width: i32, height: i32) -> Vector4<f32> {
+        Vector4::new(2.0 * x / width as f32 - 1.0, 2.0 * y / height as f32 - 1.0, 0.5, 1.0)
+    }
+
+    #[test]
+    fn whole_image_is_the_identity() {
+        assert_eq!(tile_projection(0, 0, 640, 480, 640, 480), Matrix4::identity());
+    }
+
+    #[test]
+    fn pixels_land_in_their_tile_including_the_short_edge_tiles() {
+        const SIZE: i32 = 4096;
+        let (width, height) = (5000, 3000);
+        let mut tiles = 0;
+        for y0 in (0..height).step_by(SIZE as usize) {
+            for x0 in (0..width).step_by(SIZE as usize) {
+                let (x1, y1) = ((x0 + SIZE).min(width), (y0 + SIZE).min(height));
+                let projection = tile_projection(x0, y0, x1, y1, width, height);
+                // Both corners and a pixel centre near the far corner of the tile
+                for (x, y) in [(x0 as f32, y0 as f32), (x1 as f32, y1 as f32), (x1 as f32 - 0.5, y1 as f32 - 0.5)] {
+                    let mapped = projection * ndc(x, y, width, height);
+                    let expected = ndc(x - x0 as f32, y - y0 as f32, x1 - x0, y1 - y0);
+                    assert!((mapped - expected).norm() < 1e-4, "tile at {}, {}: {} != {}", x0, y0, mapped, expected);
+                }
+                tiles += 1;
+            }
+        }
+        assert_eq!(tiles, 2);
+    }
+
+    #[test]
+    fn depth_is_left_alone() {
+        let projection = tile_projection(100, 50, 300, 250, 400, 300);
+        let mapped = projection * Vector4::new(0.0, 0.0, -0.25, 1.0);
+        assert_eq!((mapped.z, mapped.w), (-0.25, 1.0));
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+use web_sys::HtmlOptionElement;
+
+use crate::dom::{input_element, select_element};
+use crate::integrators::{Integrator, INTEGRATORS};
+use crate::shading::{TrailStyle, TRAIL_STYLES};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    // Integration step in simulated seconds
+    pub delta_t: f32,
+    pub integrator: Integrator,
+    // Integrate trajectories in a web worker instead of the render loop
+    pub use_worker: bool,
+    // Seed of the generator behind initial conditions and colours
+    pub seed: u32,
+    // Trail length in points
+    pub max_points: usize,
+    // Trail width in CSS pixels, 0 draws plain one pixel lines
+    pub line_width: f32,
+    pub trail_style: TrailStyle,
+    // Radius of shaded tubes and half width of ribbons in world units
+    pub tube_radius: f32,
+    // Trajectories created on start and restart
+    pub num_trajectories: usize,
+    // Oldest unpinned trajectories are evicted beyond this
+    pub max_trajectories: usize,
+    // Edge length of the default spawn cube
+    pub random_range: f32,
+    // Milliseconds between automatic spawns, 0 disables them
+    pub spawn_interval: u32,
+    pub camera_speed: f32,
+    pub sensitivity: f32,
+    // Trajectories leaving this box are treated as diverged, also the length of the axes
+    pub bounds: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            delta_t: 0.002,
+            integrator: Integrator::Euler,
+            use_worker: false,
+            seed: 0,
+            max_points: 500,
+            line_width: 2.0,
+            trail_style: TrailStyle::Lines,
+            tube_radius: 0.3,
+            num_trajectories: 20,
+            max_trajectories: 100,
+            random_range: 100.0,
+            spawn_interval: 500,
+            camera_speed: 2.0,
+            sensitivity: 0.1,
+            bounds: 10000.0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.delta_t.is_finite() && self.delta_t > 0.0 && self.delta_t <= 1.0) {
+            return Err(String::from("dt must be greater than 0 and at most 1"));
+        }
+        if !(2..=100_000).contains(&self.max_points) {
+            return Err(String::from("trail length must be between 2 and 100000 points"));
+        }
+        if !(self.line_width.is_finite() && (0.0..=50.0).contains(&self.line_width)) {
+            return Err(String::from("line width must be between 0 and 50 pixels"));
+        }
+        if !(self.tube_radius.is_finite() && self.tube_radius > 0.0) {
+            return Err(String::from("tube radius must be positive"));
+        }
+        if self.max_trajectories == 0 || self.max_trajectories > 10_000 {
+            return Err(String::from("maximum trajectories must be between 1 and 10000"));
+        }
+        if self.num_trajectories > self.max_trajectories {
+            return Err(String::from("initial trajectories can't exceed the maximum"));
+        }
+        if !(self.random_range.is_finite() && self.random_range >= 0.0) {
+            return Err(String::from("spawn range must not be negative"));
+        }
+        if !(self.camera_speed.is_finite() && self.camera_speed > 0.0) {
+            return Err(String::from("camera speed must be positive"));
+        }
+        if !(self.sensitivity.is_finite() && self.sensitivity > 0.0) {
+            return Err(String::from("mouse sensitivity must be positive"));
+        }
+        if !(self.bounds.is_finite() && self.bounds > 0.0) {
+            return Err(String::from("bounds must be positive"));
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: std::str::FromStr>(id: &str, name: &str) -> Result<T, String> {
+    input_element(id)
+        .value()
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("{} is not a valid number", name))
+}
+
+pub fn read_settings() -> Result<Settings, String> {
+    let settings = Settings {
+        delta_t: parse("setting-delta-t", "dt")?,
+        integrator: Integrator::parse(&select_element("setting-integrator").value()).ok_or("unknown integrator")?,
+        use_worker: input_element("setting-worker").checked(),
+        seed: parse("setting-seed", "seed")?,
+        max_points: parse("setting-max-points", "trail length")?,
+        line_width: parse("setting-line-width", "line width")?,
+        trail_style: TrailStyle::parse(&select_element("setting-trail-style").value()).ok_or("unknown trail style")?,
+        tube_radius: parse("setting-tube-radius", "tube radius")?,
+        num_trajectories: parse("setting-num-trajectories", "initial trajectories")?,
+        max_trajectories: parse("setting-max-trajectories", "maximum trajectories")?,
+        random_range: parse("setting-random-range", "spawn range")?,
+        spawn_interval: parse("setting-spawn-interval", "spawn interval")?,
+        camera_speed: parse("setting-camera-speed", "camera speed")?,
+        sensitivity: parse("setting-sensitivity", "mouse sensitivity")?,
+        bounds: parse("setting-bounds", "bounds")?,
+    };
+    settings.validate()?;
+    Ok(settings)
+}
+
+pub fn show_settings(settings: &Settings) {
+    input_element("setting-delta-t").set_value(&settings.delta_t.to_string());
+    select_element("setting-integrator").set_value(settings.integrator.id());
+    input_element("setting-worker").set_checked(settings.use_worker);
+    input_element("setting-seed").set_value(&settings.seed.to_string());
+    input_element("setting-max-points").set_value(&settings.max_points.to_string());
+    input_element("setting-line-width").set_value(&settings.line_width.to_string());
+    select_element("setting-trail-style").set_value(settings.trail_style.id());
+    input_element("setting-tube-radius").set_value(&settings.tube_radius.to_string());
+    input_element("setting-num-trajectories").set_value(&settings.num_trajectories.to_string());
+    input_element("setting-max-trajectories").set_value(&settings.max_trajectories.to_string());
+    input_element("setting-random-range").set_value(&settings.random_range.to_string());
+    input_element("setting-spawn-interval").set_value(&settings.spawn_interval.to_string());
+    input_element("setting-camera-speed").set_value(&settings.camera_speed.to_string());
+    input_element("setting-sensitivity").set_value(&settings.sensitivity.to_string());
+    input_element("setting-bounds").set_value(&settings.bounds.to_string());
+}
+
+pub fn populate_integrator_select() {
+    let select = select_element("setting-integrator");
+    for integrator in INTEGRATORS {
+        let option = HtmlOptionElement::new_with_text_and_value(integrator.name(), integrator.id()).unwrap();
+        select.append_child(&option).unwrap();
+    }
+}
+
+pub fn populate_trail_style_select() {
+    let select = select_element("setting-trail-style");
+    for style in TRAIL_STYLES {
+        let option = HtmlOptionElement::new_with_text_and_value(style.name(), style.id()).unwrap();
+        select.append_child(&option).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(change: impl FnOnce(&mut Settings)) -> String {
+        let mut settings = Settings::default();
+        change(&mut settings);
+        settings.validate().unwrap_err()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(Settings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn limits_are_inclusive() {
+        let settings = Settings { delta_t: 1.0, max_points: 2, line_width: 0.0, max_trajectories: 10_000, num_trajectories: 10_000, random_range: 0.0, ..Default::default() };
+        assert_eq!(settings.validate(), Ok(()));
+        let settings = Settings { max_points: 100_000, line_width: 50.0, max_trajectories: 1, num_trajectories: 0, ..Default::default() };
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn each_out_of_range_setting_is_named() {
+        assert_eq!(error(|settings| settings.delta_t = 0.0), "dt must be greater than 0 and at most 1");
+        assert_eq!(error(|settings| settings.delta_t = f32::NAN), "dt must be greater than 0 and at most 1");
+        assert_eq!(error(|settings| settings.max_points = 1), "trail length must be between 2 and 100000 points");
+        assert_eq!(error(|settings| settings.line_width = 50.5), "line width must be between 0 and 50 pixels");
+        assert_eq!(error(|settings| settings.tube_radius = 0.0), "tube radius must be positive");
+        assert_eq!(error(|settings| settings.max_trajectories = 0), "maximum trajectories must be between 1 and 10000");
+        assert_eq!(error(|settings| settings.max_trajectories = 10_001), "maximum trajectories must be between 1 and 10000");
+        assert_eq!(error(|settings| settings.num_trajectories = settings.max_trajectories + 1), "initial trajectories can't exceed the maximum");
+        assert_eq!(error(|settings| settings.random_range = -1.0), "spawn range must not be negative");
+        assert_eq!(error(|settings| settings.camera_speed = f32::INFINITY), "camera speed must be positive");
+        assert_eq!(error(|settings| settings.sensitivity = -0.1), "mouse sensitivity must be positive");
+        assert_eq!(error(|settings| settings.bounds = 0.0), "bounds must be positive");
+    }
+}
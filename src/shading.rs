@@ -0,0 +1,257 @@
+use std::cell::Cell;
+
+use nalgebra::{Matrix4, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlUniformLocation, WebGlVertexArrayObject};
+
+use crate::drawing::TrailRenderer;
+use crate::mesh::{tangents, transported_normals};
+use crate::webgl_utils::{compile_shader, link_program};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TrailStyle {
+    Lines,
+    Tubes,
+    Ribbons,
+}
+
+pub const TRAIL_STYLES: [TrailStyle; 3] = [TrailStyle::Lines, TrailStyle::Tubes, TrailStyle::Ribbons];
+
+impl TrailStyle {
+    pub fn id(&self) -> &'static str {
+        match self {
+            TrailStyle::Lines => "lines",
+            TrailStyle::Tubes => "tubes",
+            TrailStyle::Ribbons => "ribbons",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrailStyle::Lines => "lines",
+            TrailStyle::Tubes => "shaded tubes",
+            TrailStyle::Ribbons => "shaded ribbons",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<TrailStyle> {
+        TRAIL_STYLES.iter().copied().find(|style| style.id() == id)
+    }
+}
+
+// Sides of the tube cross section
+const TUBE_SEGMENTS: u32 = 8;
+
+// Each segment of a strip is one instance, extruded here into a section of tube or ribbon. The
+// rings at either end only depend on the neighbouring points, so consecutive sections meet exactly
+const VERTEX_SHADER_TEXT: &str =
+r##"#version 300 es
+
+// x picks the start (0) or end (1) of the segment, y is the angle around a tube or the side of a
+// ribbon (-1 or 1)
+in vec2 corner;
+in vec3 before;
+in vec3 start;
+in vec3 end;
+in vec3 after;
+// Tube normals, parallel transported along the trail
+in vec3 startNormal;
+in vec3 endNormal;
+uniform mat4 uMVP;
+uniform vec3 uEye;
+uniform float uRadius;
+uniform bool uRibbon;
+out vec3 vPosition;
+out vec3 vNormal;
+
+vec3 direction(vec3 delta) {
+    // Repeated points have no direction
+    return length(delta) > 1e-6 ? normalize(delta) : vec3(0.0, 0.0, 1.0);
+}
+
+void main() {
+    bool atEnd = corner.x > 0.5;
+    vec3 point = atEnd ? end : start;
+    vec3 tangent = direction(atEnd ? after - start : end - before);
+    vec3 position;
+    vec3 normal;
+    if (uRibbon) {
+        // Turned to face the camera. Normals lean out towards the edges so that the interpolated
+        // normal shades it like a cylinder seen from the side
+        vec3 facing = uEye - point;
+        vec3 side = cross(tangent, facing);
+        side = length(side) > 1e-6 ? normalize(side) : vec3(0.0);
+        normal = cross(side, tangent);
+        normal = length(normal) > 1e-6 ? normalize(normal) : side;
+        if (dot(normal, facing) < 0.0) {
+            normal = -normal;
+        }
+        position = point + side * (uRadius * corner.y);
+        normal += side * corner.y;
+    } else {
+        vec3 across = atEnd ? endNormal : startNormal;
+        // Already perpendicular to the tangent up to rounding
+        across = normalize(across - tangent * dot(across, tangent));
+        vec3 binormal = cross(tangent, across);
+        normal = across * cos(corner.y) + binormal * sin(corner.y);
+        position = point + normal * uRadius;
+    }
+    gl_Position = uMVP * vec4(position, 1.0);
+    vPosition = position;
+    vNormal = normal;
+}
+"##;
+
+// Phong shading with a light at the camera
+const FRAGMENT_SHADER_TEXT: &str =
+r##"#version 300 es
+
+precision highp float;
+in vec3 vPosition;
+in vec3 vNormal;
+uniform vec4 uColour;
+uniform vec3 uEye;
+out vec4 outColor;
+
+void main() {
+    vec3 view = normalize(uEye - vPosition);
+    vec3 normal = normalize(vNormal);
+    // Light both sides of ribbons and the inside of the open tube ends
+    if (dot(normal, view) < 0.0) {
+        normal = -normal;
+    }
+    float diffuse = max(dot(normal, view), 0.0);
+    float specular = pow(max(dot(reflect(-view, normal), view), 0.0), 32.0);
+    vec3 colour = uColour.rgb * (0.25 + 0.65 * diffuse) + vec3(0.4 * specular);
+    outColor = vec4(colour, uColour.a);
+}
+"##;
+
+// Corners of the two triangles joining the rings at either end of a segment, for each side of a
+// tube followed by the single quad of a ribbon
+fn corners() -> Vec<f32> {
+    let mut corners = Vec::new();
+    for j in 0..TUBE_SEGMENTS {
+        let angle = |j: u32| 2.0 * std::f32::consts::PI * j as f32 / TUBE_SEGMENTS as f32;
+        let (a, b) = (angle(j), angle(j + 1));
+        corners.extend_from_slice(&[0.0, a, 1.0, a, 1.0, b, 0.0, a, 1.0, b, 0.0, b]);
+    }
+    corners.extend_from_slice(&[0.0, -1.0, 1.0, -1.0, 1.0, 1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0]);
+    corners
+}
+
+const TUBE_VERTICES: i32 = 6 * TUBE_SEGMENTS as i32;
+const RIBBON_VERTICES: i32 = 6;
+
+// Draws trails as lit geometry extruded on the GPU from the points of the trail
+pub struct ShadedTrailRenderer {
+    program: WebGlProgram,
+    vertex_array: WebGlVertexArrayObject,
+    point_buffer: WebGlBuffer,
+    mvp_location: Option<WebGlUniformLocation>,
+    colour_location: Option<WebGlUniformLocation>,
+    eye_location: Option<WebGlUniformLocation>,
+    radius_location: Option<WebGlUniformLocation>,
+    ribbon_location: Option<WebGlUniformLocation>,
+    style: Cell<TrailStyle>,
+    radius: Cell<f32>,
+    eye: Cell<Vector3<f32>>,
+}
+
+impl ShadedTrailRenderer {
+    pub fn new(context: &WebGl2RenderingContext) -> Result<Self, String> {
+        let vert_shader = compile_shader(context, WebGl2RenderingContext::VERTEX_SHADER, VERTEX_SHADER_TEXT)?;
+        let frag_shader = compile_shader(context, WebGl2RenderingContext::FRAGMENT_SHADER, FRAGMENT_SHADER_TEXT)?;
+        let program = link_program(context, &vert_shader, &frag_shader)?;
+
+        let vertex_array = context.create_vertex_array().ok_or("Could not create vertex array object")?;
+        context.bind_vertex_array(Some(&vertex_array));
+
+        let corner_buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&corner_buffer));
+        let corners = corners();
+        unsafe {
+            let view = js_sys::Float32Array::view(&corners);
+            context.buffer_data_with_array_buffer_view(WebGl2RenderingContext::ARRAY_BUFFER, &view, WebGl2RenderingContext::STATIC_DRAW);
+        }
+        let corner = context.get_attrib_location(&program, "corner") as u32;
+        context.vertex_attrib_pointer_with_i32(corner, 2, WebGl2RenderingContext::FLOAT, false, 0, 0);
+        context.enable_vertex_attrib_array(corner);
+
+        // Each point is followed by its normal. The attributes read the same buffer one point apart,
+        // advancing once per instance
+        let point_buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&point_buffer));
+        let attributes = [("before", 0), ("start", 24), ("end", 48), ("after", 72), ("startNormal", 36), ("endNormal", 60)];
+        for (name, offset) in attributes {
+            let location = context.get_attrib_location(&program, name) as u32;
+            context.vertex_attrib_pointer_with_i32(location, 3, WebGl2RenderingContext::FLOAT, false, 24, offset);
+            context.vertex_attrib_divisor(location, 1);
+            context.enable_vertex_attrib_array(location);
+        }
+        context.bind_vertex_array(None);
+
+        Ok(ShadedTrailRenderer {
+            mvp_location: context.get_uniform_location(&program, "uMVP"),
+            colour_location: context.get_uniform_location(&program, "uColour"),
+            eye_location: context.get_uniform_location(&program, "uEye"),
+            radius_location: context.get_uniform_location(&program, "uRadius"),
+            ribbon_location: context.get_uniform_location(&program, "uRibbon"),
+            program,
+            vertex_array,
+            point_buffer,
+            style: Cell::new(TrailStyle::Tubes),
+            radius: Cell::new(0.3),
+            eye: Cell::new(Vector3::zeros()),
+        })
+    }
+
+    // Tubes or ribbons of `radius` in world units, lit from the camera at `eye`
+    pub fn set_view(&self, style: TrailStyle, radius: f32, eye: Vector3<f32>) {
+        self.style.set(style);
+        self.radius.set(radius);
+        self.eye.set(eye);
+    }
+}
+
+impl TrailRenderer for ShadedTrailRenderer {
+    fn begin(&self, context: &WebGl2RenderingContext, mvp: &Matrix4<f32>, _viewport: Vector2<f32>, _line_width: f32) {
+        context.use_program(Some(&self.program));
+        context.bind_vertex_array(Some(&self.vertex_array));
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.point_buffer));
+        context.uniform_matrix4fv_with_f32_array(self.mvp_location.as_ref(), false, mvp.as_slice());
+        let eye = self.eye.get();
+        context.uniform3f(self.eye_location.as_ref(), eye.x, eye.y, eye.z);
+        context.uniform1f(self.radius_location.as_ref(), self.radius.get());
+        context.uniform1i(self.ribbon_location.as_ref(), (self.style.get() == TrailStyle::Ribbons) as i32);
+        context.disable(WebGl2RenderingContext::BLEND);
+    }
+
+    fn set_colour(&self, context: &WebGl2RenderingContext, colour: &Vector3<f32>) {
+        context.uniform4f(self.colour_location.as_ref(), colour.x, colour.y, colour.z, 1.0);
+    }
+
+    fn draw_line_strip(&self, context: &WebGl2RenderingContext, data: &[Vector3<f32>]) {
+        let (first, last) = match (data.first(), data.last()) {
+            (Some(first), Some(last)) if data.len() >= 2 => (first, last),
+            _ => return,
+        };
+        // Tangents match the ones the shader works out, so the normals are perpendicular to them
+        let normals = transported_normals(&tangents(data));
+        // The end points are repeated so that every segment has a point on either side
+        let vertices: Vec<f32> = std::iter::once((first, &normals[0]))
+            .chain(data.iter().zip(&normals))
+            .chain(std::iter::once((last, &normals[normals.len() - 1])))
+            .flat_map(|(point, normal)| point.iter().chain(normal.iter()).copied())
+            .collect();
+        unsafe {
+            let view = js_sys::Float32Array::view(&vertices);
+            context.buffer_data_with_array_buffer_view(WebGl2RenderingContext::ARRAY_BUFFER, &view, WebGl2RenderingContext::STREAM_DRAW);
+        }
+        let (offset, count) = match self.style.get() {
+            TrailStyle::Ribbons => (TUBE_VERTICES, RIBBON_VERTICES),
+            _ => (0, TUBE_VERTICES),
+        };
+        context.draw_arrays_instanced(WebGl2RenderingContext::TRIANGLES, offset, count, data.len() as i32 - 1);
+    }
+}
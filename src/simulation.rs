@@ -0,0 +1,323 @@
+use nalgebra::Vector3;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::distributions::InitialDistribution;
+use crate::systems::DynamicalSystem;
+use crate::integrators::Integrator;
+use crate::colour::ColourScheme;
+
+// Step used to estimate directional derivatives of the flow
+const TANGENT_EPSILON: f32 = 1e-3;
+// Simulated seconds the local Lyapunov estimate is averaged over
+const LYAPUNOV_WINDOW: f32 = 1.0;
+
+pub struct Trajectory {
+    pub id: u32,
+    pub points: Vec<Vector3<f32>>,
+    // Simulation time of each point
+    pub times: Vec<f32>,
+    pub colour: Vector3<f32>,
+    pub initial: Vector3<f32>,
+    // Simulation time the trajectory was created at
+    pub spawn_time: f32,
+    pub steps: u64,
+    // Number of times the trajectory has crossed between the two wings (sign changes of x)
+    pub wing_switches: u32,
+    // Smoothed growth rate of a small perturbation, a local estimate of the largest Lyapunov exponent
+    pub local_lyapunov: f32,
+    tangent: Vector3<f32>,
+    // Pinned trajectories are never evicted
+    pub pinned: bool,
+    // Imported trails are drawn but never integrated
+    pub frozen: bool,
+}
+
+impl Trajectory {
+    pub fn head(&self) -> Vector3<f32> {
+        *self.points.last().unwrap()
+    }
+
+    // Direction of travel estimated from the last two points
+    pub fn heading(&self) -> Option<Vector3<f32>> {
+        let n = self.points.len();
+        if n < 2 {
+            return None;
+        }
+        (self.points[n - 1] - self.points[n - 2]).try_normalize(f32::EPSILON)
+    }
+
+    pub fn age(&self, time: f32) -> f32 {
+        time - self.spawn_time
+    }
+
+    // Returns false, leaving the trajectory untouched, if the step left the finite region inside `bounds`.
+    // `time` is the simulation time at the end of the step
+    fn advance<F: Fn(Vector3<f32>) -> Vector3<f32>>(&mut self, derivative: F, integrator: Integrator, delta_t: f32, time: f32, max_points: usize, bounds: f32) -> bool {
+        let new_state = integrator.step(&derivative, self.head(), delta_t);
+        if !is_within_bounds(&new_state, bounds) {
+            return false;
+        }
+        self.record(derivative, new_state, delta_t, time, max_points);
+        true
+    }
+
+    // Append a state one step of `delta_t` after the head and update the statistics
+    fn record<F: Fn(Vector3<f32>) -> Vector3<f32>>(&mut self, derivative: F, new_state: Vector3<f32>, delta_t: f32, time: f32, max_points: usize) {
+        let last = self.head();
+        let velocity = derivative(last);
+
+        // Push the tangent vector through the linearised flow and measure how much it grew
+        let flow_along_tangent = (derivative(last + self.tangent * TANGENT_EPSILON) - velocity) / TANGENT_EPSILON;
+        let tangent = self.tangent + flow_along_tangent * delta_t;
+        let growth = tangent.magnitude();
+        if growth.is_finite() && growth > 0.0 {
+            let rate = growth.ln() / delta_t;
+            let alpha = (delta_t / LYAPUNOV_WINDOW).min(1.0);
+            self.local_lyapunov += (rate - self.local_lyapunov) * alpha;
+            self.tangent = tangent / growth;
+        }
+
+        if last.x != 0.0 && new_state.x != 0.0 && last.x.signum() != new_state.x.signum() {
+            self.wing_switches += 1;
+        }
+
+        self.points.push(new_state);
+        self.times.push(time);
+        self.steps += 1;
+        if self.points.len() > max_points {
+            let excess = self.points.len() - max_points;
+            self.points.drain(..excess);
+            self.times.drain(..excess);
+        }
+    }
+}
+
+pub fn is_within_bounds(state: &Vector3<f32>, bounds: f32) -> bool {
+    state.iter().all(|c| c.is_finite() && c.abs() <= bounds)
+}
+
+pub struct Simulation {
+    pub system: &'static DynamicalSystem,
+    pub parameters: Vec<f32>,
+    pub colour_scheme: ColourScheme,
+    pub trajectories: Vec<Trajectory>,
+    // Simulated seconds since the start
+    pub time: f32,
+    pub max_trajectories: usize,
+    // Where new trajectories are started
+    pub distribution: InitialDistribution,
+    spawn_index: u64,
+    // Trajectories spawned since the last clear, used to pick colours
+    spawned: u64,
+    // Spawning draws from this generator so that a seed reproduces the same initial conditions
+    pub seed: u32,
+    rng: StdRng,
+    next_id: u32,
+}
+
+impl Simulation {
+    pub fn new(system: &'static DynamicalSystem, max_trajectories: usize, distribution: InitialDistribution, seed: u32) -> Self {
+        Simulation {
+            system,
+            parameters: system.defaults(),
+            colour_scheme: ColourScheme::Random,
+            trajectories: Vec::new(),
+            time: 0.0,
+            max_trajectories,
+            distribution,
+            spawn_index: 0,
+            spawned: 0,
+            seed,
+            rng: StdRng::seed_from_u64(seed as u64),
+            next_id: 0,
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u32) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed as u64);
+    }
+
+    pub fn set_colour_scheme(&mut self, colour_scheme: ColourScheme) {
+        self.colour_scheme = colour_scheme;
+        for (index, trajectory) in self.trajectories.iter_mut().enumerate() {
+            trajectory.colour = colour_scheme.colour(index as u64, &mut self.rng);
+        }
+    }
+
+    // Switch to another system with its default parameters, dropping the current trajectories
+    pub fn set_system(&mut self, system: &'static DynamicalSystem) {
+        self.system = system;
+        self.parameters = system.defaults();
+        self.clear();
+    }
+
+    pub fn spawn(&mut self, initial: Vector3<f32>, colour: Vector3<f32>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.trajectories.push(Trajectory {
+            id,
+            points: vec![initial],
+            times: vec![self.time],
+            colour,
+            initial,
+            spawn_time: self.time,
+            steps: 0,
+            wing_switches: 0,
+            local_lyapunov: 0.0,
+            tangent: Vector3::new(1.0, 1.0, 1.0).normalize(),
+            pinned: false,
+            frozen: false,
+        });
+        self.evict();
+        id
+    }
+
+    // Add a precomputed trail that is displayed as it is. `points` must not be empty
+    pub fn add_static(&mut self, points: Vec<Vector3<f32>>, times: Vec<f32>, colour: Vector3<f32>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let initial = points[0];
+        self.trajectories.push(Trajectory {
+            id,
+            steps: points.len() as u64 - 1,
+            spawn_time: times[0],
+            points,
+            times,
+            colour,
+            initial,
+            wing_switches: 0,
+            local_lyapunov: 0.0,
+            tangent: Vector3::new(1.0, 1.0, 1.0).normalize(),
+            pinned: true,
+            frozen: true,
+        });
+        id
+    }
+
+    pub fn next_colour(&mut self) -> Vector3<f32> {
+        let colour = self.colour_scheme.colour(self.spawned, &mut self.rng);
+        self.spawned += 1;
+        colour
+    }
+
+    pub fn set_max_trajectories(&mut self, max_trajectories: usize) {
+        self.max_trajectories = max_trajectories;
+        self.evict();
+    }
+
+    // Drop the oldest unpinned trajectories until we are back under the limit
+    fn evict(&mut self) {
+        while self.trajectories.len() > self.max_trajectories {
+            match self.trajectories.iter().position(|trajectory| !trajectory.pinned) {
+                Some(i) => { self.trajectories.remove(i); },
+                None => break,
+            }
+        }
+    }
+
+    // Spawn at `initial` with a colour from the current scheme
+    pub fn spawn_at(&mut self, initial: Vector3<f32>) -> u32 {
+        let colour = self.next_colour();
+        self.spawn(initial, colour)
+    }
+
+    pub fn spawn_random(&mut self) -> u32 {
+        let initial = self.distribution.sample(self.spawn_index, &mut self.rng);
+        self.spawn_index += 1;
+        self.spawn_at(initial)
+    }
+
+    // Remove every trajectory and rewind the random generator to the seed
+    pub fn clear(&mut self) {
+        self.trajectories.clear();
+        self.spawn_index = 0;
+        self.spawned = 0;
+        self.reseed(self.seed);
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Trajectory> {
+        self.trajectories.iter().find(|trajectory| trajectory.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Trajectory> {
+        self.trajectories.iter_mut().find(|trajectory| trajectory.id == id)
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.trajectories.retain(|trajectory| trajectory.id != id);
+    }
+
+    // Mean of every stored point, a rough centre for the attractor
+    pub fn centroid(&self) -> Vector3<f32> {
+        let (sum, count) = self.trajectories.iter()
+            .flat_map(|trajectory| trajectory.points.iter())
+            .fold((Vector3::zeros(), 0), |(sum, count), point| (sum + point, count + 1));
+        if count > 0 { sum / count as f32 } else { Vector3::zeros() }
+    }
+
+    // Append states integrated elsewhere to a trajectory, `time` is the simulation time before the first
+    pub fn extend(&mut self, id: u32, states: &[Vector3<f32>], time: f32, delta_t: f32, max_points: usize) {
+        let derivative = self.system.derivative;
+        let parameters = &self.parameters;
+        if let Some(trajectory) = self.trajectories.iter_mut().find(|trajectory| trajectory.id == id) {
+            for (i, state) in states.iter().enumerate() {
+                trajectory.record(|state| derivative(&state, parameters), *state, delta_t, time + (i + 1) as f32 * delta_t, max_points);
+            }
+        }
+    }
+
+    // Advance every trajectory, removing the ones that blew up. Returns how many were removed
+    pub fn step(&mut self, integrator: Integrator, delta_t: f32, max_points: usize, bounds: f32) -> usize {
+        let count = self.trajectories.len();
+        let derivative = self.system.derivative;
+        let parameters = &self.parameters;
+        let time = self.time + delta_t;
+        self.trajectories.retain_mut(|trajectory| trajectory.frozen || trajectory.advance(|state| derivative(&state, parameters), integrator, delta_t, time, max_points, bounds));
+        self.time = time;
+        count - self.trajectories.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::SYSTEMS;
+
+    fn simulation() -> Simulation {
+        Simulation::new(&SYSTEMS[0], 10, InitialDistribution::cube(1.0), 0)
+    }
+
+    #[test]
+    fn states_past_the_bounds_are_removed_and_counted() {
+        let mut simulation = simulation();
+        let inside = simulation.spawn(Vector3::new(1.0, 1.0, 1.0), Vector3::zeros());
+        // y grows by about 270 in one step
+        simulation.spawn(Vector3::new(999.0, 999.0, 0.0), Vector3::zeros());
+        simulation.spawn(Vector3::new(0.0, -2000.0, 0.0), Vector3::zeros());
+        // Imported trails are never integrated, so never removed
+        let frozen = simulation.add_static(vec![Vector3::new(5000.0, 0.0, 0.0)], vec![0.0], Vector3::zeros());
+
+        assert_eq!(simulation.step(Integrator::Euler, 0.01, 100, 1000.0), 2);
+        let ids: Vec<u32> = simulation.trajectories.iter().map(|trajectory| trajectory.id).collect();
+        assert_eq!(ids, vec![inside, frozen]);
+        assert_eq!(simulation.get(inside).unwrap().points.len(), 2);
+    }
+
+    #[test]
+    fn unstable_steps_are_removed_before_they_become_infinite() {
+        let mut simulation = simulation();
+        let unstable = simulation.spawn(Vector3::new(1.0, 1.0, 1.0), Vector3::zeros());
+        let mut removed = 0;
+        // Explicit Euler with dt = 1 is far outside its stability region for Lorenz
+        for _ in 0..100 {
+            removed += simulation.step(Integrator::Euler, 1.0, 100, f32::INFINITY);
+            if let Some(trajectory) = simulation.get(unstable) {
+                assert!(trajectory.points.iter().all(|point| point.iter().all(|c| c.is_finite())));
+            }
+        }
+        assert_eq!(removed, 1);
+        assert!(simulation.get(unstable).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+
+use crate::colour::to_hex;
+
+// Clip-space points with z < -w are behind the near plane
+fn near_distance(clip: &Vector4<f32>) -> f32 {
+    clip.z + clip.w
+}
+
+fn to_screen(clip: &Vector4<f32>, width: f32, height: f32) -> Vector2<f32> {
+    Vector2::new((clip.x / clip.w + 1.0) * 0.5 * width, (1.0 - clip.y / clip.w) * 0.5 * height)
+}
+
+// Project a polyline, splitting it wherever it passes behind the near plane
+fn project_polyline(points: &[Vector3<f32>], mvp: &Matrix4<f32>, width: f32, height: f32) -> Vec<Vec<Vector2<f32>>> {
+    let mut runs: Vec<Vec<Vector2<f32>>> = Vec::new();
+    let mut current: Vec<Vector2<f32>> = Vec::new();
+    let mut previous: Option<Vector4<f32>> = None;
+    for point in points {
+        let clip = mvp * point.push(1.0);
+        let inside = near_distance(&clip) > 0.0;
+        if let Some(previous) = previous {
+            let previous_inside = near_distance(&previous) > 0.0;
+            if inside != previous_inside {
+                // Where the segment meets the near plane
+                let t = near_distance(&previous) / (near_distance(&previous) - near_distance(&clip));
+                let crossing = previous.lerp(&clip, t);
+                current.push(to_screen(&crossing, width, height));
+                if previous_inside {
+                    runs.push(std::mem::take(&mut current));
+                }
+            }
+        }
+        if inside {
+            current.push(to_screen(&clip, width, height));
+        }
+        previous = Some(clip);
+    }
+    runs.push(current);
+    runs.retain(|run| run.len() >= 2);
+    runs
+}
+
+pub struct SvgWriter {
+    svg: String,
+    mvp: Matrix4<f32>,
+    width: f32,
+    height: f32,
+}
+
+impl SvgWriter {
+    pub fn new(mvp: Matrix4<f32>, width: f32, height: f32) -> Self {
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", width, height);
+        svg.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"#000000\"/>\n", width, height));
+        SvgWriter { svg, mvp, width, height }
+    }
+
+    pub fn polyline(&mut self, points: &[Vector3<f32>], colour: &Vector3<f32>, stroke_width: f32) {
+        for run in project_polyline(points, &self.mvp, self.width, self.height) {
+            let coordinates: Vec<String> = run.iter().map(|point| format!("{:.2},{:.2}", point.x, point.y)).collect();
+            self.svg.push_str(&format!(
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linejoin=\"round\"/>\n",
+                coordinates.join(" "), to_hex(colour), stroke_width));
+        }
+    }
+
+    pub fn finish(mut self) -> String {
+        self.svg.push_str("</svg>\n");
+        self.svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: f32 = 100.0;
+
+    // Camera at the origin looking down -z with a 90 degree field of view and the near plane at z = -1
+    fn mvp() -> Matrix4<f32> {
+        Matrix4::new_perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0)
+    }
+
+    fn assert_close(actual: &[Vector2<f32>], expected: &[(f32, f32)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (actual, (x, y)) in actual.iter().zip(expected) {
+            assert!((actual.x - x).abs() < 1e-3 && (actual.y - y).abs() < 1e-3, "{:?} != {:?}", actual, (x, y));
+        }
+    }
+
+    #[test]
+    fn segment_crossing_the_near_plane_is_clipped() {
+        // x = 1 at z = -3 is a third of the way to the edge, at the near plane it is on the edge
+        let runs = project_polyline(&[Vector3::new(1.0, 0.0, -3.0), Vector3::new(1.0, 0.0, 3.0)], &mvp(), SIZE, SIZE);
+        assert_eq!(runs.len(), 1);
+        assert_close(&runs[0], &[(200.0 / 3.0, 50.0), (100.0, 50.0)]);
+    }
+
+    #[test]
+    fn polyline_is_split_where_it_passes_behind_the_camera() {
+        let front = Vector3::new(1.0, 0.0, -3.0);
+        let behind = Vector3::new(1.0, 0.0, 3.0);
+        let runs = project_polyline(&[behind, front, behind, front], &mvp(), SIZE, SIZE);
+        assert_eq!(runs.len(), 2);
+        assert_close(&runs[0], &[(100.0, 50.0), (200.0 / 3.0, 50.0), (100.0, 50.0)]);
+        assert_close(&runs[1], &[(100.0, 50.0), (200.0 / 3.0, 50.0)]);
+    }
+
+    #[test]
+    fn polyline_behind_the_camera_is_dropped() {
+        let runs = project_polyline(&[Vector3::new(0.0, 1.0, 2.0), Vector3::new(0.0, -1.0, 5.0)], &mvp(), SIZE, SIZE);
+        assert!(runs.is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+use web_sys::HtmlOptionElement;
+
+use crate::dom::{input_element, select_element};
+use crate::systems::DynamicalSystem;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SweepShape {
+    // Straight line from `from` to `to`
+    Ramp,
+    // Swings between `from` and `to` and back once per duration
+    Sine,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ParameterSweep {
+    // Index into the system's parameters
+    pub index: usize,
+    pub from: f32,
+    pub to: f32,
+    // Seconds
+    pub duration: f32,
+    pub shape: SweepShape,
+    // Restart a ramp once it reaches the end
+    pub looping: bool,
+    // Seconds swept so far, only advanced while the simulation runs
+    time: f32,
+}
+
+impl ParameterSweep {
+    pub fn new(index: usize, from: f32, to: f32, duration: f32, shape: SweepShape, looping: bool) -> Self {
+        ParameterSweep { index, from, to, duration, shape, looping, time: 0.0 }
+    }
+
+    pub fn value(&self) -> f32 {
+        let t = self.time / self.duration;
+        let fraction = match self.shape {
+            SweepShape::Ramp if self.looping => t.fract(),
+            SweepShape::Ramp => t.min(1.0),
+            SweepShape::Sine => 0.5 - 0.5 * (2.0 * std::f32::consts::PI * t).cos(),
+        };
+        self.from + (self.to - self.from) * fraction
+    }
+
+    // Move the sweep on by `elapsed` seconds, returns the new value and whether the sweep has finished
+    pub fn advance(&mut self, elapsed: f32) -> (f32, bool) {
+        self.time += elapsed;
+        let finished = self.shape == SweepShape::Ramp && !self.looping && self.time >= self.duration;
+        (self.value(), finished)
+    }
+}
+
+pub fn populate_sweep_parameters(system: &DynamicalSystem) {
+    let select = select_element("sweep-parameter");
+    select.set_inner_html("");
+    for (index, parameter) in system.parameters.iter().enumerate() {
+        let option = HtmlOptionElement::new_with_text_and_value(parameter.name, &index.to_string()).unwrap();
+        select.append_child(&option).unwrap();
+    }
+    show_sweep_range(system);
+}
+
+// Fill the range boxes with the full range of the chosen parameter
+pub fn show_sweep_range(system: &DynamicalSystem) {
+    let index: usize = select_element("sweep-parameter").value().parse().unwrap_or(0);
+    if let Some(parameter) = system.parameters.get(index) {
+        input_element("sweep-from").set_value(&parameter.min.to_string());
+        input_element("sweep-to").set_value(&parameter.max.to_string());
+    }
+}
+
+fn number(id: &str, name: &str) -> Result<f32, String> {
+    input_element(id)
+        .value()
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| format!("{} must be a number", name))
+}
+
+pub fn read_sweep(system: &DynamicalSystem) -> Result<ParameterSweep, String> {
+    let index: usize = select_element("sweep-parameter").value().parse().map_err(|_| "choose a parameter to sweep")?;
+    if index >= system.parameters.len() {
+        return Err(String::from("choose a parameter to sweep"));
+    }
+    let duration = number("sweep-duration", "duration")?;
+    if duration <= 0.0 {
+        return Err(String::from("duration must be positive"));
+    }
+    let shape = match select_element("sweep-shape").value().as_str() {
+        "sine" => SweepShape::Sine,
+        _ => SweepShape::Ramp,
+    };
+    Ok(ParameterSweep::new(index, number("sweep-from", "from")?, number("sweep-to", "to")?, duration, shape, input_element("sweep-loop").checked()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_finish_exactly_on_the_end_value() {
+        let mut sweep = ParameterSweep::new(0, 10.0, 30.0, 2.0, SweepShape::Ramp, false);
+        assert_eq!(sweep.advance(1.0), (20.0, false));
+        // Overshooting the duration still stops on `to`
+        assert_eq!(sweep.advance(1.5), (30.0, true));
+        assert_eq!(sweep.advance(1.0), (30.0, true));
+    }
+
+    #[test]
+    fn looping_ramps_wrap_without_finishing() {
+        let mut sweep = ParameterSweep::new(0, 10.0, 30.0, 2.0, SweepShape::Ramp, true);
+        assert_eq!(sweep.advance(1.5), (25.0, false));
+        assert_eq!(sweep.advance(1.0), (15.0, false));
+        assert_eq!(sweep.advance(1.5), (10.0, false));
+    }
+
+    #[test]
+    fn sines_stay_within_the_range() {
+        let mut sweep = ParameterSweep::new(0, -1.0, 3.0, 2.0, SweepShape::Sine, false);
+        let values: Vec<f32> = (0..200).map(|_| {
+            let (value, finished) = sweep.advance(0.05);
+            assert!(!finished);
+            value
+        }).collect();
+        assert!(values.iter().all(|value| (-1.0..=3.0).contains(value)));
+        // Both ends are reached, once per duration
+        assert!(values.iter().any(|value| *value < -0.99));
+        assert!(values.iter().any(|value| *value > 2.99));
+    }
+}
@@ -0,0 +1,179 @@
+use nalgebra::Vector3;
+
+// The GPU integrator passes parameters as a fixed size array
+pub const MAX_PARAMETERS: usize = 6;
+
+pub struct Parameter {
+    pub name: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub step: f32,
+    pub default: f32,
+}
+
+pub struct DynamicalSystem {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub parameters: &'static [Parameter],
+    pub derivative: fn(&Vector3<f32>, &[f32]) -> Vector3<f32>,
+    // The same derivative as the body of a GLSL `vec3 derivative(vec3 s, float p[MAX_PARAMETERS])`
+    pub glsl: &'static str,
+    // Edge length of the cube new trajectories are spawned in
+    pub spawn_range: f32,
+    // Distance the camera starts at to see the whole attractor
+    pub camera_distance: f32,
+}
+
+impl DynamicalSystem {
+    pub fn defaults(&self) -> Vec<f32> {
+        self.parameters.iter().map(|parameter| parameter.default).collect()
+    }
+
+    pub fn describe(&self, values: &[f32]) -> String {
+        self.parameters.iter()
+            .zip(values)
+            .map(|(parameter, value)| format!("{} = {}", parameter.name, value))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+fn lorentz(state: &Vector3<f32>, p: &[f32]) -> Vector3<f32> {
+    let (sigma, rho, beta) = (p[0], p[1], p[2]);
+    Vector3::new(
+        sigma * (state.y - state.x),
+        state.x * (rho - state.z) - state.y,
+        state.x * state.y - beta * state.z,
+    )
+}
+
+fn rossler(state: &Vector3<f32>, p: &[f32]) -> Vector3<f32> {
+    let (a, b, c) = (p[0], p[1], p[2]);
+    Vector3::new(
+        -state.y - state.z,
+        state.x + a * state.y,
+        b + state.z * (state.x - c),
+    )
+}
+
+fn chen(state: &Vector3<f32>, p: &[f32]) -> Vector3<f32> {
+    let (a, b, c) = (p[0], p[1], p[2]);
+    Vector3::new(
+        a * (state.y - state.x),
+        (c - a) * state.x - state.x * state.z + c * state.y,
+        state.x * state.y - b * state.z,
+    )
+}
+
+fn thomas(state: &Vector3<f32>, p: &[f32]) -> Vector3<f32> {
+    let b = p[0];
+    Vector3::new(
+        state.y.sin() - b * state.x,
+        state.z.sin() - b * state.y,
+        state.x.sin() - b * state.z,
+    )
+}
+
+fn aizawa(state: &Vector3<f32>, p: &[f32]) -> Vector3<f32> {
+    let (a, b, c, d, e, f) = (p[0], p[1], p[2], p[3], p[4], p[5]);
+    let (x, y, z) = (state.x, state.y, state.z);
+    Vector3::new(
+        (z - b) * x - d * y,
+        d * x + (z - b) * y,
+        c + a * z - z * z * z / 3.0 - (x * x + y * y) * (1.0 + e * z) + f * z * x * x * x,
+    )
+}
+
+fn halvorsen(state: &Vector3<f32>, p: &[f32]) -> Vector3<f32> {
+    let a = p[0];
+    let (x, y, z) = (state.x, state.y, state.z);
+    Vector3::new(
+        -a * x - 4.0 * y - 4.0 * z - y * y,
+        -a * y - 4.0 * z - 4.0 * x - z * z,
+        -a * z - 4.0 * x - 4.0 * y - x * x,
+    )
+}
+
+pub const SYSTEMS: &[DynamicalSystem] = &[
+    DynamicalSystem {
+        id: "lorenz",
+        name: "Lorenz",
+        parameters: &[
+            Parameter { name: "sigma", min: 0.1, max: 100.0, step: 0.1, default: 10.0 },
+            Parameter { name: "rho", min: 0.1, max: 200.0, step: 0.01, default: 28.0 },
+            Parameter { name: "beta", min: 0.1, max: 100.0, step: 0.01, default: 8.0 / 3.0 },
+        ],
+        derivative: lorentz,
+        glsl: "return vec3(p[0] * (s.y - s.x), s.x * (p[1] - s.z) - s.y, s.x * s.y - p[2] * s.z);",
+        spawn_range: 100.0,
+        camera_distance: 500.0,
+    },
+    DynamicalSystem {
+        id: "rossler",
+        name: "Rössler",
+        parameters: &[
+            Parameter { name: "a", min: 0.0, max: 1.0, step: 0.01, default: 0.2 },
+            Parameter { name: "b", min: 0.0, max: 2.0, step: 0.01, default: 0.2 },
+            Parameter { name: "c", min: 0.1, max: 20.0, step: 0.1, default: 5.7 },
+        ],
+        derivative: rossler,
+        glsl: "return vec3(-s.y - s.z, s.x + p[0] * s.y, p[1] + s.z * (s.x - p[2]));",
+        spawn_range: 20.0,
+        camera_distance: 100.0,
+    },
+    DynamicalSystem {
+        id: "chen",
+        name: "Chen",
+        parameters: &[
+            Parameter { name: "a", min: 0.1, max: 60.0, step: 0.1, default: 35.0 },
+            Parameter { name: "b", min: 0.1, max: 10.0, step: 0.01, default: 3.0 },
+            Parameter { name: "c", min: 0.1, max: 40.0, step: 0.1, default: 28.0 },
+        ],
+        derivative: chen,
+        glsl: "return vec3(p[0] * (s.y - s.x), (p[2] - p[0]) * s.x - s.x * s.z + p[2] * s.y, s.x * s.y - p[1] * s.z);",
+        spawn_range: 40.0,
+        camera_distance: 200.0,
+    },
+    DynamicalSystem {
+        id: "thomas",
+        name: "Thomas",
+        parameters: &[
+            Parameter { name: "b", min: 0.0, max: 0.5, step: 0.001, default: 0.208186 },
+        ],
+        derivative: thomas,
+        glsl: "return sin(s.yzx) - p[0] * s;",
+        spawn_range: 8.0,
+        camera_distance: 25.0,
+    },
+    DynamicalSystem {
+        id: "aizawa",
+        name: "Aizawa",
+        parameters: &[
+            Parameter { name: "a", min: 0.0, max: 2.0, step: 0.01, default: 0.95 },
+            Parameter { name: "b", min: 0.0, max: 2.0, step: 0.01, default: 0.7 },
+            Parameter { name: "c", min: 0.0, max: 2.0, step: 0.01, default: 0.6 },
+            Parameter { name: "d", min: 0.0, max: 10.0, step: 0.01, default: 3.5 },
+            Parameter { name: "e", min: 0.0, max: 1.0, step: 0.01, default: 0.25 },
+            Parameter { name: "f", min: 0.0, max: 1.0, step: 0.01, default: 0.1 },
+        ],
+        derivative: aizawa,
+        glsl: "return vec3((s.z - p[1]) * s.x - p[3] * s.y, p[3] * s.x + (s.z - p[1]) * s.y, p[2] + p[0] * s.z - s.z * s.z * s.z / 3.0 - (s.x * s.x + s.y * s.y) * (1.0 + p[4] * s.z) + p[5] * s.z * s.x * s.x * s.x);",
+        spawn_range: 1.0,
+        camera_distance: 6.0,
+    },
+    DynamicalSystem {
+        id: "halvorsen",
+        name: "Halvorsen",
+        parameters: &[
+            Parameter { name: "a", min: 0.5, max: 3.0, step: 0.01, default: 1.89 },
+        ],
+        derivative: halvorsen,
+        glsl: "return vec3(-p[0] * s.x - 4.0 * s.y - 4.0 * s.z - s.y * s.y, -p[0] * s.y - 4.0 * s.z - 4.0 * s.x - s.z * s.z, -p[0] * s.z - 4.0 * s.x - 4.0 * s.y - s.x * s.x);",
+        spawn_range: 10.0,
+        camera_distance: 50.0,
+    },
+];
+
+pub fn find_system(id: &str) -> Option<&'static DynamicalSystem> {
+    SYSTEMS.iter().find(|system| system.id == id)
+}
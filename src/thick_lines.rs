@@ -0,0 +1,175 @@
+use nalgebra::{Matrix4, Vector2, Vector3};
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlUniformLocation, WebGlVertexArrayObject};
+
+use crate::drawing::TrailRenderer;
+use crate::webgl_utils::{compile_shader, link_program};
+
+// Each segment of a strip is one instance. Its quad is expanded in screen space to the line width
+// plus round caps, and the fragment shader cuts a capsule out of it. Overlapping caps form the joins
+const VERTEX_SHADER_TEXT: &str =
+r##"#version 300 es
+
+// x runs from the start (0) to the end (1) of the segment, y from one side (-1) to the other (1)
+in vec2 corner;
+in vec3 start;
+in vec3 end;
+uniform mat4 uMVP;
+uniform vec2 uViewport;
+uniform float uWidth;
+// Pixel position relative to the start of the segment, along and across it
+out vec2 vLocal;
+out float vLength;
+
+void main() {
+    vec4 a = uMVP * vec4(start, 1.0);
+    vec4 b = uMVP * vec4(end, 1.0);
+
+    // Clip to the near plane so that points behind the camera don't flip across the screen
+    float da = a.z + a.w;
+    float db = b.z + b.w;
+    if (da < 0.0 && db < 0.0) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
+    if (da < 0.0) {
+        a = mix(a, b, da / (da - db));
+    }
+    if (db < 0.0) {
+        b = mix(b, a, db / (db - da));
+    }
+
+    vec2 sa = (a.xy / a.w * 0.5 + 0.5) * uViewport;
+    vec2 sb = (b.xy / b.w * 0.5 + 0.5) * uViewport;
+    vec2 delta = sb - sa;
+    float len = length(delta);
+    vec2 dir = len > 1e-4 ? delta / len : vec2(1.0, 0.0);
+    vec2 normal = vec2(-dir.y, dir.x);
+
+    // Half the width plus a pixel for the antialiased edge
+    float pad = uWidth * 0.5 + 1.0;
+    float along = corner.x * (len + 2.0 * pad) - pad;
+    float across = corner.y * pad;
+    vec2 screen = sa + dir * along + normal * across;
+    float depth = mix(a.z / a.w, b.z / b.w, corner.x);
+
+    gl_Position = vec4(screen / uViewport * 2.0 - 1.0, depth, 1.0);
+    vLocal = vec2(along, across);
+    vLength = len;
+}
+"##;
+
+const FRAGMENT_SHADER_TEXT: &str =
+r##"#version 300 es
+
+precision highp float;
+in vec2 vLocal;
+in float vLength;
+uniform vec4 uColour;
+uniform float uWidth;
+// Whether to draw the partly covered edge rather than the solid core
+uniform bool uFringe;
+out vec4 outColor;
+
+void main() {
+    // Distance to the segment, round beyond its ends
+    float distance = length(vec2(vLocal.x - clamp(vLocal.x, 0.0, vLength), vLocal.y));
+    float coverage = clamp(uWidth * 0.5 + 0.5 - distance, 0.0, 1.0);
+    if (coverage <= 0.0 || (coverage < 1.0) != uFringe) {
+        discard;
+    }
+    outColor = vec4(uColour.rgb, uColour.a * coverage);
+}
+"##;
+
+// Two triangles covering the unit quad in (along, across) coordinates
+const CORNERS: [f32; 12] = [0.0, -1.0, 1.0, -1.0, 1.0, 1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0];
+
+pub struct ThickLineRenderer {
+    program: WebGlProgram,
+    vertex_array: WebGlVertexArrayObject,
+    point_buffer: WebGlBuffer,
+    mvp_location: Option<WebGlUniformLocation>,
+    colour_location: Option<WebGlUniformLocation>,
+    viewport_location: Option<WebGlUniformLocation>,
+    width_location: Option<WebGlUniformLocation>,
+    fringe_location: Option<WebGlUniformLocation>,
+}
+
+impl ThickLineRenderer {
+    pub fn new(context: &WebGl2RenderingContext) -> Result<Self, String> {
+        let vert_shader = compile_shader(context, WebGl2RenderingContext::VERTEX_SHADER, VERTEX_SHADER_TEXT)?;
+        let frag_shader = compile_shader(context, WebGl2RenderingContext::FRAGMENT_SHADER, FRAGMENT_SHADER_TEXT)?;
+        let program = link_program(context, &vert_shader, &frag_shader)?;
+
+        let vertex_array = context.create_vertex_array().ok_or("Could not create vertex array object")?;
+        context.bind_vertex_array(Some(&vertex_array));
+
+        let corner_buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&corner_buffer));
+        unsafe {
+            let corners = js_sys::Float32Array::view(&CORNERS);
+            context.buffer_data_with_array_buffer_view(WebGl2RenderingContext::ARRAY_BUFFER, &corners, WebGl2RenderingContext::STATIC_DRAW);
+        }
+        let corner = context.get_attrib_location(&program, "corner") as u32;
+        context.vertex_attrib_pointer_with_i32(corner, 2, WebGl2RenderingContext::FLOAT, false, 0, 0);
+        context.enable_vertex_attrib_array(corner);
+
+        // Start and end read the same buffer one point apart, advancing once per instance
+        let point_buffer = context.create_buffer().ok_or("Failed to create buffer")?;
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&point_buffer));
+        for (name, offset) in [("start", 0), ("end", 12)] {
+            let location = context.get_attrib_location(&program, name) as u32;
+            context.vertex_attrib_pointer_with_i32(location, 3, WebGl2RenderingContext::FLOAT, false, 12, offset);
+            context.vertex_attrib_divisor(location, 1);
+            context.enable_vertex_attrib_array(location);
+        }
+        context.bind_vertex_array(None);
+
+        Ok(ThickLineRenderer {
+            mvp_location: context.get_uniform_location(&program, "uMVP"),
+            colour_location: context.get_uniform_location(&program, "uColour"),
+            viewport_location: context.get_uniform_location(&program, "uViewport"),
+            width_location: context.get_uniform_location(&program, "uWidth"),
+            fringe_location: context.get_uniform_location(&program, "uFringe"),
+            program,
+            vertex_array,
+            point_buffer,
+        })
+    }
+}
+
+impl TrailRenderer for ThickLineRenderer {
+    fn begin(&self, context: &WebGl2RenderingContext, mvp: &Matrix4<f32>, viewport: Vector2<f32>, line_width: f32) {
+        context.use_program(Some(&self.program));
+        context.bind_vertex_array(Some(&self.vertex_array));
+        context.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&self.point_buffer));
+        context.uniform_matrix4fv_with_f32_array(self.mvp_location.as_ref(), false, mvp.as_slice());
+        context.uniform2f(self.viewport_location.as_ref(), viewport.x, viewport.y);
+        context.uniform1f(self.width_location.as_ref(), line_width);
+        context.enable(WebGl2RenderingContext::BLEND);
+        context.blend_func(WebGl2RenderingContext::SRC_ALPHA, WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA);
+    }
+
+    fn set_colour(&self, context: &WebGl2RenderingContext, colour: &Vector3<f32>) {
+        context.uniform4f(self.colour_location.as_ref(), colour.x, colour.y, colour.z, 1.0);
+    }
+
+    fn draw_line_strip(&self, context: &WebGl2RenderingContext, data: &[Vector3<f32>]) {
+        if data.len() < 2 {
+            return;
+        }
+        let vertices: Vec<f32> = data.iter().flat_map(|point| point.iter().copied()).collect();
+        unsafe {
+            let positions = js_sys::Float32Array::view(&vertices);
+            context.buffer_data_with_array_buffer_view(WebGl2RenderingContext::ARRAY_BUFFER, &positions, WebGl2RenderingContext::STREAM_DRAW);
+        }
+        // The solid core writes depth. The antialiased edge goes on top without writing depth,
+        // otherwise its nearly transparent pixels would hide the trails drawn after it
+        for fringe in [false, true] {
+            context.uniform1i(self.fringe_location.as_ref(), fringe as i32);
+            context.depth_mask(!fringe);
+            context.draw_arrays_instanced(WebGl2RenderingContext::TRIANGLES, 0, 6, data.len() as i32 - 1);
+        }
+        context.depth_mask(true);
+    }
+}
@@ -1,8 +1,10 @@
-use web_sys::{WebGl2RenderingContext, WebGlProgram, WebGlShader};
+use wasm_bindgen::JsValue;
+use web_sys::{HtmlCanvasElement, WebGl2RenderingContext, WebGlProgram, WebGlShader};
 
 pub fn clear(context: &WebGl2RenderingContext) {
     context.clear_color(0.0, 0.0, 0.0, 1.0);
-    context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+    context.clear_depth(1.0);
+    context.clear(WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT);
 }
 
 pub fn compile_shader(
@@ -33,6 +35,16 @@ pub fn link_program(
     context: &WebGl2RenderingContext,
     vert_shader: &WebGlShader,
     frag_shader: &WebGlShader,
+) -> Result<WebGlProgram, String> {
+    link_program_with_varyings(context, vert_shader, frag_shader, &[])
+}
+
+// Like `link_program`, capturing the named vertex shader outputs with transform feedback
+pub fn link_program_with_varyings(
+    context: &WebGl2RenderingContext,
+    vert_shader: &WebGlShader,
+    frag_shader: &WebGlShader,
+    varyings: &[&str],
 ) -> Result<WebGlProgram, String> {
     let program = context
         .create_program()
@@ -40,6 +52,10 @@ pub fn link_program(
 
     context.attach_shader(&program, vert_shader);
     context.attach_shader(&program, frag_shader);
+    if !varyings.is_empty() {
+        let names: js_sys::Array = varyings.iter().map(|name| JsValue::from_str(name)).collect();
+        context.transform_feedback_varyings(&program, &names, WebGl2RenderingContext::SEPARATE_ATTRIBS);
+    }
     context.link_program(&program);
 
     if context
@@ -54,3 +70,17 @@ pub fn link_program(
             .unwrap_or_else(|| String::from("Unknown error creating program object")))
     }
 }
+
+// Match the drawing buffer to the displayed size of the canvas, returns the new aspect ratio
+pub fn resize_canvas(canvas: &HtmlCanvasElement, context: &WebGl2RenderingContext, device_pixel_ratio: f64) -> f32 {
+    let width = ((canvas.client_width() as f64 * device_pixel_ratio).round() as u32).max(1);
+    let height = ((canvas.client_height() as f64 * device_pixel_ratio).round() as u32).max(1);
+
+    if canvas.width() != width || canvas.height() != height {
+        canvas.set_width(width);
+        canvas.set_height(height);
+    }
+
+    context.viewport(0, 0, width as i32, height as i32);
+    width as f32 / height as f32
+}
@@ -0,0 +1,482 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use gloo::events::EventListener;
+use gloo::timers::callback::Interval;
+use js_sys::{Array, Float32Array};
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{DedicatedWorkerGlobalScope, MessageEvent, Worker, WorkerOptions, WorkerType};
+
+use crate::integrators::Integrator;
+use crate::settings::Settings;
+use crate::simulation::{is_within_bounds, Simulation};
+use crate::systems::{find_system, DynamicalSystem, SYSTEMS};
+
+// Loads the wasm module and calls `worker_start`
+const WORKER_SCRIPT: &str = "./worker.js";
+// Milliseconds between batches, about one step per displayed frame
+const TICK_INTERVAL: u32 = 16;
+// Most steps taken in one batch when the worker falls behind
+const MAX_STEPS_PER_TICK: u32 = 10;
+// Batches the page hasn't acknowledged yet before the worker stops ticking. The page only
+// acknowledges when it draws, so a hidden or throttled tab doesn't pile up batches
+const MAX_IN_FLIGHT: u32 = 4;
+// Values before the first trajectory in a batch
+const HEADER_LENGTH: usize = 5;
+const READY_MESSAGE: &str = "ready";
+
+// What the worker integrates, changing it restarts the worker from the page's states
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct Configuration {
+    system: String,
+    integrator: Integrator,
+}
+
+// Settings that can change every frame, e.g. during a sweep. States integrated before a change
+// arrives are still kept
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct Tuning {
+    parameters: Vec<f32>,
+    delta_t: f32,
+    bounds: f32,
+    paused: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Command {
+    // Replaces the trajectories too, they are spawned again from the page's copy. Batches from
+    // earlier generations are dropped as they were integrated with the old configuration
+    Configure { generation: u32, configuration: Configuration },
+    Tune(Tuning),
+    // Simulation time the worker continues from
+    SetTime(f32),
+    Spawn { id: u32, state: [f32; 3] },
+    Remove(u32),
+    // The page applied this many batches
+    Received(u32),
+}
+
+#[derive(Debug, PartialEq)]
+struct TrajectoryUpdate {
+    id: u32,
+    // Left the bounds after `states`
+    diverged: bool,
+    states: Vec<Vector3<f32>>,
+}
+
+// New states of every trajectory from one tick of the worker
+#[derive(Debug, PartialEq)]
+struct Batch {
+    generation: u32,
+    // Simulation time before the first step
+    time: f32,
+    delta_t: f32,
+    steps: u32,
+    trajectories: Vec<TrajectoryUpdate>,
+}
+
+impl Batch {
+    // Flattened so it can be transferred as a Float32Array:
+    // [generation, start time, dt, steps, trajectories, then per trajectory: id, diverged, count, x, y, z, ...].
+    // The generation and ids are stored by their bits so they survive the trip exactly
+    fn encode(&self) -> Vec<f32> {
+        let mut data = vec![f32::from_bits(self.generation), self.time, self.delta_t, self.steps as f32, self.trajectories.len() as f32];
+        for trajectory in &self.trajectories {
+            data.extend([f32::from_bits(trajectory.id), if trajectory.diverged { 1.0 } else { 0.0 }, trajectory.states.len() as f32]);
+            data.extend(trajectory.states.iter().flat_map(|state| state.iter().copied()));
+        }
+        data
+    }
+
+    // None if `data` is cut short
+    fn decode(data: &[f32]) -> Option<Batch> {
+        let header = data.get(..HEADER_LENGTH)?;
+        let mut trajectories = Vec::with_capacity(header[4] as usize);
+        let mut offset = HEADER_LENGTH;
+        for _ in 0..header[4] as usize {
+            let fields = data.get(offset..offset + 3)?;
+            let count = fields[2] as usize;
+            let states = data.get(offset + 3..offset + 3 + count * 3)?.chunks_exact(3).map(Vector3::from_column_slice).collect();
+            trajectories.push(TrajectoryUpdate { id: fields[0].to_bits(), diverged: fields[1] != 0.0, states });
+            offset += 3 + count * 3;
+        }
+        Some(Batch { generation: header[0].to_bits(), time: header[1], delta_t: header[2], steps: header[3] as u32, trajectories })
+    }
+}
+
+// Worker side, only the head of each trajectory is kept
+struct WorkerState {
+    system: &'static DynamicalSystem,
+    configuration: Option<Configuration>,
+    tuning: Option<Tuning>,
+    generation: u32,
+    time: f32,
+    heads: Vec<(u32, Vector3<f32>)>,
+    last_tick: Option<f64>,
+    in_flight: u32,
+}
+
+impl WorkerState {
+    fn new() -> Self {
+        WorkerState { system: &SYSTEMS[0], configuration: None, tuning: None, generation: 0, time: 0.0, heads: Vec::new(), last_tick: None, in_flight: 0 }
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Configure { generation, configuration } => {
+                self.system = find_system(&configuration.system).unwrap_or(&SYSTEMS[0]);
+                self.configuration = Some(configuration);
+                self.generation = generation;
+                self.heads.clear();
+            },
+            Command::Tune(tuning) => self.tuning = Some(tuning),
+            Command::SetTime(time) => self.time = time,
+            Command::Spawn { id, state } => self.heads.push((id, Vector3::from(state))),
+            Command::Remove(id) => self.heads.retain(|(head, _)| *head != id),
+            Command::Received(count) => self.in_flight = self.in_flight.saturating_sub(count),
+        }
+    }
+
+    // Integrate every head, dropping the ones that diverge
+    fn tick(&mut self, now: f64) -> Option<Batch> {
+        let elapsed = now - self.last_tick.unwrap_or(now - TICK_INTERVAL as f64);
+        self.last_tick = Some(now);
+        if self.in_flight >= MAX_IN_FLIGHT {
+            return None;
+        }
+        let integrator = self.configuration.as_ref()?.integrator;
+        let tuning = self.tuning.as_ref().filter(|tuning| !tuning.paused)?;
+        let steps = ((elapsed / TICK_INTERVAL as f64).round() as u32).clamp(1, MAX_STEPS_PER_TICK);
+        let derivative = self.system.derivative;
+        let parameters = &tuning.parameters;
+
+        let mut trajectories = Vec::with_capacity(self.heads.len());
+        self.heads.retain_mut(|(id, head)| {
+            let mut states = Vec::with_capacity(steps as usize);
+            let mut diverged = false;
+            for _ in 0..steps {
+                let next = integrator.step(|state| derivative(&state, parameters), *head, tuning.delta_t);
+                if !is_within_bounds(&next, tuning.bounds) {
+                    diverged = true;
+                    break;
+                }
+                *head = next;
+                states.push(next);
+            }
+            trajectories.push(TrajectoryUpdate { id: *id, diverged, states });
+            !diverged
+        });
+        let batch = Batch { generation: self.generation, time: self.time, delta_t: tuning.delta_t, steps, trajectories };
+        self.time += steps as f32 * tuning.delta_t;
+        self.in_flight += 1;
+        Some(batch)
+    }
+}
+
+// Entry point of the simulation worker, see worker.js
+#[wasm_bindgen]
+pub fn worker_start() {
+    console_error_panic_hook::set_once();
+    let scope: DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+    let state = Rc::new(RefCell::new(WorkerState::new()));
+
+    let state_message_internal = state.clone();
+    EventListener::new(&scope, "message", move |event| {
+        let event = event.unchecked_ref::<MessageEvent>();
+        let commands: Vec<Command> = match event.data().as_string().and_then(|text| serde_json::from_str(&text).ok()) {
+            Some(commands) => commands,
+            None => return,
+        };
+        let mut state = state_message_internal.borrow_mut();
+        for command in commands {
+            state.apply(command);
+        }
+    }).forget();
+
+    let scope_tick_internal = scope.clone();
+    Interval::new(TICK_INTERVAL, move || {
+        if let Some(batch) = state.borrow_mut().tick(js_sys::Date::now()) {
+            // Hand the buffer over instead of copying it
+            let array = Float32Array::from(batch.encode().as_slice());
+            let _ = scope_tick_internal.post_message_with_transfer(&array, &Array::of1(&array.buffer()));
+        }
+    }).forget();
+
+    let _ = scope.post_message(&JsValue::from_str(READY_MESSAGE));
+}
+
+// Tracks what the worker has been told and turns its batches into trajectory points, apart
+// from the messaging so it can be tested without a worker
+#[derive(Default)]
+struct WorkerLink {
+    // Trajectories the worker is integrating
+    known: HashSet<u32>,
+    configuration: Option<Configuration>,
+    tuning: Option<Tuning>,
+    // Counts configurations sent, batches carry the one they were integrated with
+    generation: u32,
+    // Batches applied since the last acknowledgement
+    received: u32,
+}
+
+impl WorkerLink {
+    // Commands bringing the worker up to date with the page
+    fn commands(&mut self, simulation: &Simulation, settings: &Settings, paused: bool) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if self.received > 0 {
+            commands.push(Command::Received(self.received));
+            self.received = 0;
+        }
+        let configuration = Configuration { system: simulation.system.id.to_string(), integrator: settings.integrator };
+        if self.configuration.as_ref() != Some(&configuration) {
+            // Start the worker again from the states shown on the page
+            self.generation += 1;
+            commands.push(Command::Configure { generation: self.generation, configuration: configuration.clone() });
+            commands.push(Command::SetTime(simulation.time));
+            self.configuration = Some(configuration);
+            self.tuning = None;
+            self.known.clear();
+        }
+        let tuning = Tuning { parameters: simulation.parameters.clone(), delta_t: settings.delta_t, bounds: settings.bounds, paused };
+        if self.tuning.as_ref() != Some(&tuning) {
+            commands.push(Command::Tune(tuning.clone()));
+            self.tuning = Some(tuning);
+        }
+
+        let live: HashSet<u32> = simulation.trajectories.iter().filter(|trajectory| !trajectory.frozen).map(|trajectory| trajectory.id).collect();
+        for trajectory in simulation.trajectories.iter().filter(|trajectory| live.contains(&trajectory.id) && !self.known.contains(&trajectory.id)) {
+            let head = trajectory.head();
+            commands.push(Command::Spawn { id: trajectory.id, state: [head.x, head.y, head.z] });
+        }
+        commands.extend(self.known.difference(&live).map(|id| Command::Remove(*id)));
+        self.known = live;
+        commands
+    }
+
+    // Apply one batch unless it is from an earlier configuration, returns how many trajectories diverged
+    fn receive(&mut self, simulation: &mut Simulation, update: &[f32], max_points: usize) -> usize {
+        self.received += 1;
+        let batch = match Batch::decode(update) {
+            Some(batch) if batch.generation == self.generation => batch,
+            _ => return 0,
+        };
+        let mut diverged = 0;
+        for trajectory in batch.trajectories {
+            simulation.extend(trajectory.id, &trajectory.states, batch.time, batch.delta_t, max_points);
+            if trajectory.diverged && simulation.get(trajectory.id).is_some() {
+                simulation.remove(trajectory.id);
+                self.known.remove(&trajectory.id);
+                diverged += 1;
+            }
+        }
+        simulation.time = batch.time + batch.steps as f32 * batch.delta_t;
+        diverged
+    }
+}
+
+// Main thread side. Keeps the worker in step with the simulation and applies the states it sends
+pub struct SimulationWorker {
+    worker: Worker,
+    ready: Rc<Cell<bool>>,
+    updates: Rc<RefCell<Vec<Vec<f32>>>>,
+    link: WorkerLink,
+    _listener: EventListener,
+}
+
+impl SimulationWorker {
+    pub fn new() -> Result<Self, String> {
+        let options = WorkerOptions::new();
+        options.set_type(WorkerType::Module);
+        let worker = Worker::new_with_options(WORKER_SCRIPT, &options).map_err(|e| format!("Could not start the simulation worker: {:?}", e))?;
+
+        let ready = Rc::new(Cell::new(false));
+        let updates: Rc<RefCell<Vec<Vec<f32>>>> = Default::default();
+        let ready_internal = ready.clone();
+        let updates_internal = updates.clone();
+        let listener = EventListener::new(&worker, "message", move |event| {
+            let data = event.unchecked_ref::<MessageEvent>().data();
+            if data.as_string().as_deref() == Some(READY_MESSAGE) {
+                ready_internal.set(true);
+            } else if let Ok(array) = data.dyn_into::<Float32Array>() {
+                updates_internal.borrow_mut().push(array.to_vec());
+            }
+        });
+
+        Ok(SimulationWorker { worker, ready, updates, link: WorkerLink::default(), _listener: listener })
+    }
+
+    // Send whatever changed since the last call
+    pub fn sync(&mut self, simulation: &Simulation, settings: &Settings, paused: bool) {
+        if !self.ready.get() {
+            return;
+        }
+        let commands = self.link.commands(simulation, settings, paused);
+        if !commands.is_empty() {
+            let _ = self.worker.post_message(&JsValue::from_str(&serde_json::to_string(&commands).unwrap()));
+        }
+    }
+
+    // Apply the batches received since the last call, returns how many trajectories diverged
+    pub fn receive(&mut self, simulation: &mut Simulation, max_points: usize) -> usize {
+        let link = &mut self.link;
+        self.updates.borrow_mut().drain(..).map(|update| link.receive(simulation, &update, max_points)).sum()
+    }
+}
+
+impl Drop for SimulationWorker {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributions::InitialDistribution;
+
+    fn configuration() -> Configuration {
+        Configuration { system: String::from("lorenz"), integrator: Integrator::Rk4 }
+    }
+
+    fn tuning(paused: bool) -> Tuning {
+        Tuning { parameters: vec![10.0, 28.0, 8.0 / 3.0], delta_t: 0.01, bounds: 1000.0, paused }
+    }
+
+    fn configured(generation: u32) -> WorkerState {
+        let mut state = WorkerState::new();
+        state.apply(Command::Configure { generation, configuration: configuration() });
+        state.apply(Command::Tune(tuning(false)));
+        state.apply(Command::SetTime(2.0));
+        state.apply(Command::Spawn { id: 7, state: [1.0, 1.0, 1.0] });
+        state.apply(Command::Spawn { id: u32::MAX - 1, state: [-3.0, 2.0, 20.0] });
+        state
+    }
+
+    #[test]
+    fn batch_round_trip() {
+        let batch = Batch {
+            generation: 3,
+            time: 1.5,
+            delta_t: 0.01,
+            steps: 2,
+            trajectories: vec![
+                TrajectoryUpdate { id: 0, diverged: false, states: vec![Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.0, 5.0, 6.0)] },
+                TrajectoryUpdate { id: 123_456, diverged: true, states: vec![Vector3::new(-1.0, 0.5, 1e6)] },
+                TrajectoryUpdate { id: 9, diverged: true, states: vec![] },
+            ],
+        };
+        let data = batch.encode();
+        assert_eq!(data.len(), HEADER_LENGTH + 3 * 3 + 3 * 3);
+        assert_eq!(Batch::decode(&data), Some(batch));
+    }
+
+    #[test]
+    fn truncated_batches_are_rejected() {
+        let data = configured(1).tick(0.0).unwrap().encode();
+        for length in [0, HEADER_LENGTH - 1, HEADER_LENGTH + 2, data.len() - 1] {
+            assert_eq!(Batch::decode(&data[..length]), None, "length {}", length);
+        }
+    }
+
+    #[test]
+    fn tick_steps_every_head_and_decodes() {
+        let mut state = configured(4);
+        state.tick(0.0);
+        let batch = Batch::decode(&state.tick(TICK_INTERVAL as f64 * 3.0).unwrap().encode()).unwrap();
+        assert_eq!(batch.generation, 4);
+        assert_eq!(batch.steps, 3);
+        assert!((batch.time - 2.01).abs() < 1e-6);
+        assert_eq!(batch.trajectories.iter().map(|trajectory| trajectory.id).collect::<Vec<_>>(), vec![7, u32::MAX - 1]);
+        for trajectory in &batch.trajectories {
+            assert!(!trajectory.diverged);
+            assert_eq!(trajectory.states.len(), 3);
+        }
+        assert_eq!(state.heads[0].1, batch.trajectories[0].states[2]);
+    }
+
+    #[test]
+    fn diverged_heads_are_reported_and_dropped() {
+        let mut state = configured(1);
+        state.apply(Command::Spawn { id: 8, state: [999.9, 0.0, 0.0] });
+        let batch = state.tick(0.0).unwrap();
+        assert_eq!(batch.trajectories[2], TrajectoryUpdate { id: 8, diverged: true, states: vec![] });
+        assert_eq!(state.heads.len(), 2);
+    }
+
+    #[test]
+    fn ticks_stop_until_batches_are_acknowledged() {
+        let mut state = configured(1);
+        for i in 0..MAX_IN_FLIGHT {
+            assert!(state.tick(i as f64 * TICK_INTERVAL as f64).is_some());
+        }
+        assert!(state.tick(1000.0).is_none());
+        state.apply(Command::Received(1));
+        assert!(state.tick(1016.0).is_some());
+    }
+
+    #[test]
+    fn configure_replaces_the_heads() {
+        let mut state = configured(1);
+        state.apply(Command::Configure { generation: 2, configuration: configuration() });
+        assert!(state.heads.is_empty());
+        assert_eq!(state.generation, 2);
+        state.apply(Command::Tune(tuning(true)));
+        // Paused
+        assert!(state.tick(0.0).is_none());
+    }
+
+    fn simulation() -> Simulation {
+        let mut simulation = Simulation::new(&SYSTEMS[0], 10, InitialDistribution::cube(10.0), 1);
+        for _ in 0..3 {
+            simulation.spawn_random();
+        }
+        simulation
+    }
+
+    // Run the page and worker sides against each other for `frames`, calling `change` with the
+    // frame before each sync. Returns the number of points of each trajectory afterwards
+    fn run(simulation: &mut Simulation, settings: &mut Settings, frames: usize, mut change: impl FnMut(usize, &mut Simulation, &mut Settings)) -> Vec<usize> {
+        let mut link = WorkerLink::default();
+        let mut state = WorkerState::new();
+        // Each batch is received a frame late, after the next change was sent, as in the render loop
+        let mut pending: Option<Vec<f32>> = None;
+        for frame in 0..frames {
+            change(frame, simulation, settings);
+            for command in link.commands(simulation, settings, false) {
+                state.apply(command);
+            }
+            if let Some(batch) = pending.take() {
+                link.receive(simulation, &batch, 1000);
+            }
+            pending = Some(state.tick(frame as f64 * TICK_INTERVAL as f64).unwrap().encode());
+        }
+        link.receive(simulation, &pending.unwrap(), 1000);
+        simulation.trajectories.iter().map(|trajectory| trajectory.points.len()).collect()
+    }
+
+    #[test]
+    fn sweeping_parameters_keeps_the_batches() {
+        let mut simulation = simulation();
+        let mut settings = Settings::default();
+        let points = run(&mut simulation, &mut settings, 20, |frame, simulation, settings| {
+            simulation.parameters[1] = 28.0 + frame as f32 * 0.1;
+            settings.delta_t = 0.002 + frame as f32 * 1e-5;
+        });
+        assert_eq!(points, vec![21; 3]);
+        assert!((simulation.time - 0.002 * 20.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn changing_system_drops_the_batches_in_flight() {
+        let mut simulation = simulation();
+        let mut settings = Settings::default();
+        let integrators = [Integrator::Euler, Integrator::Rk4];
+        let points = run(&mut simulation, &mut settings, 4, |frame, _, settings| settings.integrator = integrators[frame % 2]);
+        // Only the batch received after the last change is applied
+        assert_eq!(points, vec![2; 3]);
+    }
+}
@@ -0,0 +1,5 @@
+// Runs the simulation off the main thread, see src/worker.rs
+import init, {worker_start} from './pkg/lorenz.js'
+
+await init();
+worker_start();